@@ -1,6 +1,7 @@
 use strawberry::cmd::data::UvcUacPayload;
-use strawberry::cmd::{generic, CommandHandler};
+use strawberry::cmd::CommandHandler;
 use strawberry::frame::Frame;
+use strawberry::pcm::AudioBuffer;
 use strawberry::Streamer;
 use ffmpeg_next::codec::Context;
 use ffmpeg_next::ffi::EAGAIN;
@@ -10,7 +11,7 @@ use ffmpeg_next::{format, frame};
 use snafu::OptionExt;
 use snafu::{Report, ResultExt};
 use std::process::Termination;
-use tokio::time::{Duration, Instant};
+use tokio::time::Duration;
 use strawberry_x264::{Colorspace, Image, Plane};
 
 struct MyFrame(frame::Video);
@@ -34,6 +35,10 @@ impl Frame for MyFrame {
     }
 }
 
+fn pts(frame: &frame::Frame, time_base: f64) -> Duration {
+    Duration::from_secs_f64(frame.timestamp().unwrap_or(0).max(0) as f64 * time_base)
+}
+
 #[snafu::report]
 async fn uvc_handler(cmd_handler: CommandHandler) -> Result<(), snafu::Whatever> {
     let mut state = UvcUacPayload::default();
@@ -44,11 +49,7 @@ async fn uvc_handler(cmd_handler: CommandHandler) -> Result<(), snafu::Whatever>
                 .await
                 .whatever_context("send uvc uac")?;
 
-            state.mic_volume = resp.mic_volume.get().into();
-            state.mic_jack_volume = resp.mic_jack_volume.get().into();
-            state.mic_enable = resp.mic_enabled;
-            state.cam_power_freq = resp.cam_power_freq;
-            state.cam_auto_expo = resp.cam_auto_expo;
+            state.update(&resp);
             tokio::time::sleep(Duration::from_secs(1)).await;
         }
     })
@@ -61,8 +62,10 @@ async fn launch_uvc() -> Result<(), snafu::Whatever> {
     let cmd_handler = CommandHandler::new()
         .await
         .whatever_context("command handler")?;
-    let resp = cmd_handler.command(&generic::GetUicFirmware).await.whatever_context("get uic firmware")?;
-    eprintln!("{resp:?}");
+    match cmd_handler.uic_config().await {
+        Ok(config) => eprintln!("{config:?}"),
+        Err(e) => eprintln!("{}", Report::from_error(e)),
+    }
     tokio::spawn(async move { uvc_handler(cmd_handler).await.report() });
     Ok(())
 }
@@ -80,11 +83,13 @@ async fn main() -> Result<(), snafu::Whatever> {
         .best(Type::Video)
         .whatever_context("No video stream")?;
     let video_idx = input_video.index();
+    let video_time_base = f64::from(input_video.time_base());
     let video_ctx = Context::from_parameters(input_video.parameters())
         .whatever_context("video ctx")?;
     let input_audio = input.streams().best(Type::Audio)
         .whatever_context("No audio stream")?;
     let audio_idx = input_audio.index();
+    let audio_time_base = f64::from(input_audio.time_base());
     let mut video_decoder = video_ctx
         .decoder()
         .video()
@@ -92,7 +97,6 @@ async fn main() -> Result<(), snafu::Whatever> {
     let audio_ctx = Context::from_parameters(input_audio.parameters()).whatever_context("audio ctx")?;
     let mut audio_decoder = audio_ctx.decoder().audio().whatever_context("audio decoder")?;
     let streamer = Streamer::new().await.whatever_context("gamepad streamer")?;
-    let mut tick = Instant::now();
 
     for (stream, packet) in input.packets() {
         if stream.index() == video_idx {
@@ -101,11 +105,11 @@ async fn main() -> Result<(), snafu::Whatever> {
                 let mut frame = frame::Video::empty();
                 match video_decoder.receive_frame(&mut frame) {
                     Ok(()) => {
+                        let pts = pts(&frame, video_time_base);
                         streamer
-                            .push_frame(MyFrame(frame))
+                            .push_frame_at(MyFrame(frame), pts)
+                            .await
                             .whatever_context("streaming")?;
-                        tick += Duration::from_millis(1000 / 25);
-                        tokio::time::sleep_until(tick).await;
                     }
                     Err(ffmpeg_next::Error::Other { errno }) if errno == EAGAIN => break,
                     Err(e) => {
@@ -119,12 +123,13 @@ async fn main() -> Result<(), snafu::Whatever> {
                 let mut frame = frame::Audio::empty();
                 match audio_decoder.receive_frame(&mut frame) {
                     Ok(()) => {
-                        let data = frame.plane::<f32>(0).iter()
-                            .zip(frame.plane::<f32>(1))
-                            .flat_map(|(l, r)| [*l, *r])
-                            .map(|s| (s * (i16::MAX as f32)) as i16)
-                            .flat_map(|s| s.to_le_bytes());
-                        streamer.push_audio(data);
+                        let pts = pts(&frame, audio_time_base);
+                        let planes: Vec<&[f32]> = (0..frame.planes())
+                            .map(|i| frame.plane::<f32>(i))
+                            .collect();
+                        streamer
+                            .push_samples_at(AudioBuffer::Planar(&planes), frame.rate(), pts)
+                            .whatever_context("pushing audio")?;
                     }
                     Err(ffmpeg_next::Error::Other { errno }) if errno == EAGAIN => break,
                     Err(e) => {
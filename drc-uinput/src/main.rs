@@ -0,0 +1,259 @@
+use evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
+use evdev::{
+    AbsInfo, AbsoluteAxisType, AttributeSet, BusType, EventType, InputEvent, InputId, Key,
+    PropType, RelativeAxisType, UinputAbsSetup,
+};
+use snafu::{Report, ResultExt};
+use std::time::Instant;
+use strawberry::cmd::CommandHandler;
+use strawberry::motion::{MotionState, MotionTracker};
+use strawberry::pointer::{GyroPointer, PointerConfig, PointerEvent};
+use strawberry::state::{GamepadButtons, GamepadState, InputConfig};
+use strawberry::touch::{PANEL_HEIGHT, PANEL_WIDTH};
+use strawberry::InputHub;
+
+const NAME: &str = "Wii U GamePad";
+const STICK_MAX: i32 = 32767;
+/// Motion axis units per g and per deg/s, as the kernel's own motion sensor drivers report them
+const ACCEL_RESOLUTION: i32 = 4096;
+const GYRO_RESOLUTION: i32 = 1024;
+const ACCEL_MAX: i32 = 8 * ACCEL_RESOLUTION;
+const GYRO_MAX: i32 = 2000 * GYRO_RESOLUTION;
+
+/// Button layout of the Linux gamepad spec, which SDL maps without a database entry.
+/// A and B sit on the right and bottom face buttons, like on a Nintendo pad
+const BUTTONS: [(GamepadButtons, Key); 13] = [
+    (GamepadButtons::A, Key::BTN_EAST),
+    (GamepadButtons::B, Key::BTN_SOUTH),
+    (GamepadButtons::X, Key::BTN_NORTH),
+    (GamepadButtons::Y, Key::BTN_WEST),
+    (GamepadButtons::L, Key::BTN_TL),
+    (GamepadButtons::R, Key::BTN_TR),
+    (GamepadButtons::ZL, Key::BTN_TL2),
+    (GamepadButtons::ZR, Key::BTN_TR2),
+    (GamepadButtons::MINUS, Key::BTN_SELECT),
+    (GamepadButtons::PLUS, Key::BTN_START),
+    (GamepadButtons::HOME, Key::BTN_MODE),
+    (GamepadButtons::L3, Key::BTN_THUMBL),
+    (GamepadButtons::R3, Key::BTN_THUMBR),
+];
+
+/// Mouse buttons of the gyro mouse
+const MOUSE_BUTTONS: [(GamepadButtons, Key); 2] = [
+    (GamepadButtons::ZR, Key::BTN_LEFT),
+    (GamepadButtons::R, Key::BTN_RIGHT),
+];
+
+fn input_id() -> InputId {
+    InputId::new(BusType::BUS_VIRTUAL, 0, 0, 1)
+}
+
+fn axis(axis: AbsoluteAxisType, min: i32, max: i32, resolution: i32) -> UinputAbsSetup {
+    UinputAbsSetup::new(axis, AbsInfo::new(0, min, max, 0, 0, resolution))
+}
+
+fn gamepad() -> std::io::Result<VirtualDevice> {
+    let mut keys = AttributeSet::<Key>::new();
+    for (_, key) in BUTTONS {
+        keys.insert(key);
+    }
+    VirtualDeviceBuilder::new()?
+        .name(NAME)
+        .input_id(input_id())
+        .with_keys(&keys)?
+        .with_absolute_axis(&axis(AbsoluteAxisType::ABS_X, -STICK_MAX, STICK_MAX, 0))?
+        .with_absolute_axis(&axis(AbsoluteAxisType::ABS_Y, -STICK_MAX, STICK_MAX, 0))?
+        .with_absolute_axis(&axis(AbsoluteAxisType::ABS_RX, -STICK_MAX, STICK_MAX, 0))?
+        .with_absolute_axis(&axis(AbsoluteAxisType::ABS_RY, -STICK_MAX, STICK_MAX, 0))?
+        .with_absolute_axis(&axis(AbsoluteAxisType::ABS_HAT0X, -1, 1, 0))?
+        .with_absolute_axis(&axis(AbsoluteAxisType::ABS_HAT0Y, -1, 1, 0))?
+        .build()
+}
+
+fn touchscreen() -> std::io::Result<VirtualDevice> {
+    let mut keys = AttributeSet::<Key>::new();
+    keys.insert(Key::BTN_TOUCH);
+    let mut properties = AttributeSet::<PropType>::new();
+    properties.insert(PropType::DIRECT);
+    VirtualDeviceBuilder::new()?
+        .name(&format!("{NAME} Touchscreen"))
+        .input_id(input_id())
+        .with_properties(&properties)?
+        .with_keys(&keys)?
+        .with_absolute_axis(&axis(AbsoluteAxisType::ABS_X, 0, PANEL_WIDTH as i32 - 1, 0))?
+        .with_absolute_axis(&axis(AbsoluteAxisType::ABS_Y, 0, PANEL_HEIGHT as i32 - 1, 0))?
+        .with_absolute_axis(&axis(AbsoluteAxisType::ABS_PRESSURE, 0, 0x0fff, 0))?
+        .build()
+}
+
+fn motion_sensors() -> std::io::Result<VirtualDevice> {
+    let mut properties = AttributeSet::<PropType>::new();
+    properties.insert(PropType::ACCELEROMETER);
+    let accel = |axis_type| axis(axis_type, -ACCEL_MAX, ACCEL_MAX, ACCEL_RESOLUTION);
+    let gyro = |axis_type| axis(axis_type, -GYRO_MAX, GYRO_MAX, GYRO_RESOLUTION);
+    VirtualDeviceBuilder::new()?
+        .name(&format!("{NAME} Motion Sensors"))
+        .input_id(input_id())
+        .with_properties(&properties)?
+        .with_absolute_axis(&accel(AbsoluteAxisType::ABS_X))?
+        .with_absolute_axis(&accel(AbsoluteAxisType::ABS_Y))?
+        .with_absolute_axis(&accel(AbsoluteAxisType::ABS_Z))?
+        .with_absolute_axis(&gyro(AbsoluteAxisType::ABS_RX))?
+        .with_absolute_axis(&gyro(AbsoluteAxisType::ABS_RY))?
+        .with_absolute_axis(&gyro(AbsoluteAxisType::ABS_RZ))?
+        .build()
+}
+
+fn mouse() -> std::io::Result<VirtualDevice> {
+    let mut keys = AttributeSet::<Key>::new();
+    for (_, key) in MOUSE_BUTTONS {
+        keys.insert(key);
+    }
+    let mut axes = AttributeSet::<RelativeAxisType>::new();
+    axes.insert(RelativeAxisType::REL_X);
+    axes.insert(RelativeAxisType::REL_Y);
+    VirtualDeviceBuilder::new()?
+        .name(&format!("{NAME} Gyro Mouse"))
+        .input_id(input_id())
+        .with_keys(&keys)?
+        .with_relative_axes(&axes)?
+        .build()
+}
+
+fn abs(axis: AbsoluteAxisType, value: i32) -> InputEvent {
+    InputEvent::new(EventType::ABSOLUTE, axis.0, value)
+}
+
+fn key(key: Key, pressed: bool) -> InputEvent {
+    InputEvent::new(EventType::KEY, key.code(), pressed as i32)
+}
+
+/// The kernel drops events that don't change anything, so the full state is sent every time
+fn gamepad_events(state: &GamepadState) -> Vec<InputEvent> {
+    let stick = |value: f32| (value * STICK_MAX as f32).round() as i32;
+    let direction = |negative, positive| {
+        state.pressed(positive) as i32 - state.pressed(negative) as i32
+    };
+    let mut events: Vec<_> = BUTTONS
+        .iter()
+        .map(|&(button, code)| key(code, state.pressed(button)))
+        .collect();
+    // evdev's y axes point down
+    events.extend([
+        abs(AbsoluteAxisType::ABS_X, stick(state.left_stick.x)),
+        abs(AbsoluteAxisType::ABS_Y, stick(-state.left_stick.y)),
+        abs(AbsoluteAxisType::ABS_RX, stick(state.right_stick.x)),
+        abs(AbsoluteAxisType::ABS_RY, stick(-state.right_stick.y)),
+        abs(AbsoluteAxisType::ABS_HAT0X, direction(GamepadButtons::LEFT, GamepadButtons::RIGHT)),
+        abs(AbsoluteAxisType::ABS_HAT0Y, direction(GamepadButtons::UP, GamepadButtons::DOWN)),
+    ]);
+    events
+}
+
+fn touch_events(state: &GamepadState) -> Vec<InputEvent> {
+    match state.touch {
+        Some(touch) => vec![
+            key(Key::BTN_TOUCH, true),
+            abs(AbsoluteAxisType::ABS_X, touch.x.round() as i32),
+            abs(AbsoluteAxisType::ABS_Y, touch.y.round() as i32),
+            abs(AbsoluteAxisType::ABS_PRESSURE, touch.pressure as i32),
+        ],
+        None => vec![
+            key(Key::BTN_TOUCH, false),
+            abs(AbsoluteAxisType::ABS_PRESSURE, 0),
+        ],
+    }
+}
+
+/// Relative devices can't jump to the screen center, so recentering isn't forwarded
+fn mouse_events(state: &GamepadState, pointer: &[PointerEvent]) -> Vec<InputEvent> {
+    let mut events: Vec<_> = MOUSE_BUTTONS
+        .iter()
+        .map(|&(button, code)| key(code, state.pressed(button)))
+        .collect();
+    for event in pointer {
+        if let PointerEvent::Move { dx, dy } = *event {
+            events.push(InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_X.0, dx));
+            events.push(InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_Y.0, dy));
+        }
+    }
+    events
+}
+
+fn motion_events(motion: &MotionState) -> Vec<InputEvent> {
+    let accel = |value: f32| (value * ACCEL_RESOLUTION as f32).round() as i32;
+    let gyro = |value: f32| (value * GYRO_RESOLUTION as f32).round() as i32;
+    vec![
+        abs(AbsoluteAxisType::ABS_X, accel(motion.accel.x)),
+        abs(AbsoluteAxisType::ABS_Y, accel(motion.accel.y)),
+        abs(AbsoluteAxisType::ABS_Z, accel(motion.accel.z)),
+        abs(AbsoluteAxisType::ABS_RX, gyro(motion.gyro.x)),
+        abs(AbsoluteAxisType::ABS_RY, gyro(motion.gyro.y)),
+        abs(AbsoluteAxisType::ABS_RZ, gyro(motion.gyro.z)),
+    ]
+}
+
+/// Usage: drc-uinput [--motion] [--gyro-mouse]
+///
+/// The gyro mouse moves while ZL is held, ZR and R click
+#[snafu::report]
+#[tokio::main]
+async fn main() -> Result<(), snafu::Whatever> {
+    let with_motion = std::env::args().any(|arg| arg == "--motion");
+    let with_mouse = std::env::args().any(|arg| arg == "--gyro-mouse");
+    let hub = InputHub::new().await.whatever_context("opening input")?;
+    let mut reader = hub.reader();
+    let cmd_handler = CommandHandler::new().await.whatever_context("command handler")?;
+    let mut config = InputConfig::default();
+    match cmd_handler.uic_config().await {
+        Ok(uic_config) => uic_config.apply(&mut config),
+        Err(e) => eprintln!("using default calibration: {}", Report::from_error(e)),
+    }
+
+    let mut gamepad = gamepad().whatever_context("creating gamepad device")?;
+    let mut touchscreen = touchscreen().whatever_context("creating touchscreen device")?;
+    let mut motion = match with_motion {
+        true => Some(motion_sensors().whatever_context("creating motion sensor device")?),
+        false => None,
+    };
+    let mut mouse = match with_mouse {
+        true => Some((
+            mouse().whatever_context("creating mouse device")?,
+            GyroPointer::new(PointerConfig {
+                clutch: GamepadButtons::ZL,
+                ..Default::default()
+            }),
+        )),
+        false => None,
+    };
+    let mut tracker = MotionTracker::default();
+    let mut pointer_events = Vec::new();
+
+    loop {
+        let data = reader.read().await.whatever_context("reading input")?;
+        let state = GamepadState::new(&data, &config);
+        gamepad
+            .emit(&gamepad_events(&state))
+            .whatever_context("gamepad event")?;
+        touchscreen
+            .emit(&touch_events(&state))
+            .whatever_context("touchscreen event")?;
+        if motion.is_none() && mouse.is_none() {
+            continue;
+        }
+        let time = Instant::now();
+        let motion_state = tracker.update(&data, time);
+        if let Some(device) = &mut motion {
+            device
+                .emit(&motion_events(&motion_state))
+                .whatever_context("motion event")?;
+        }
+        if let Some((device, pointer)) = &mut mouse {
+            pointer.update(&state, &motion_state, time, &mut pointer_events);
+            device
+                .emit(&mouse_events(&state, &pointer_events))
+                .whatever_context("mouse event")?;
+            pointer_events.clear();
+        }
+    }
+}
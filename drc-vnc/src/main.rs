@@ -1,5 +1,5 @@
 use strawberry::cmd::data::UvcUacPayload;
-use strawberry::cmd::{CommandHandler, generic};
+use strawberry::cmd::CommandHandler;
 use strawberry::frame::Frame;
 use strawberry::{Streamer, StreamerError};
 use image::{GenericImage, GenericImageView, ImageError, RgbaImage};
@@ -25,11 +25,7 @@ async fn uvc_handler(cmd_handler: CommandHandler) -> Result<(), snafu::Whatever>
                 .await
                 .whatever_context("send uvc uac")?;
 
-            state.mic_volume = resp.mic_volume.get().into();
-            state.mic_jack_volume = resp.mic_jack_volume.get().into();
-            state.mic_enable = resp.mic_enabled;
-            state.cam_power_freq = resp.cam_power_freq;
-            state.cam_auto_expo = resp.cam_auto_expo;
+            state.update(&resp);
             tokio::time::sleep(Duration::from_secs(1)).await;
         }
     })
@@ -42,11 +38,10 @@ async fn launch_uvc() -> Result<(), snafu::Whatever> {
     let cmd_handler = CommandHandler::new()
         .await
         .whatever_context("command handler")?;
-    let resp = cmd_handler
-        .command(&generic::GetUicFirmware)
-        .await
-        .whatever_context("get uic firmware")?;
-    eprintln!("{resp:?}");
+    match cmd_handler.uic_config().await {
+        Ok(config) => eprintln!("{config:?}"),
+        Err(e) => eprintln!("{}", Report::from_error(e)),
+    }
     tokio::spawn(async move { uvc_handler(cmd_handler).await.report() });
     Ok(())
 }
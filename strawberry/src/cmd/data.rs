@@ -135,6 +135,17 @@ impl Default for UvcUacPayload {
     }
 }
 
+impl UvcUacPayload {
+    /// Take over the settings the pad reports, so the next command keeps them
+    pub fn update(&mut self, response: &UvcUacResponse) {
+        self.mic_volume = response.mic_volume.get().into();
+        self.mic_jack_volume = response.mic_jack_volume.get().into();
+        self.mic_enable = response.mic_enabled;
+        self.cam_power_freq = response.cam_power_freq;
+        self.cam_auto_expo = response.cam_auto_expo;
+    }
+}
+
 impl Payload for UvcUacPayload {
     type Response = UvcUacResponse;
     const QUERY_TYPE: u16 = 1;
@@ -1,5 +1,7 @@
 use zerocopy::{big_endian, FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned};
-use crate::cmd::data::Payload;
+use crate::cmd::data::{CommandHeader, CommandPacket, Payload};
+use crate::cmd::{CommandHandler, Error, GenericFailedSnafu, ResponseSizeSnafu};
+use snafu::ensure;
 
 #[repr(C)]
 #[derive(Debug, FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
@@ -21,13 +23,31 @@ pub struct GenericCommandPacket<T: ?Sized> {
     payload: T
 }
 
+impl<T> GenericCommandPacket<T> {
+    /// Nonzero when the pad rejected the command
+    pub fn error_code(&self) -> u16 {
+        self.header.error_code.get()
+    }
+
+    pub fn payload(&self) -> &T {
+        &self.payload
+    }
+}
+
+/// A command of one of the pad's generic services. Commands without a payload
+/// only need the ids
 pub trait GenericPayload {
     type Response: FromBytes + KnownLayout + Immutable + Unaligned;
     const SERVICE_ID: u8;
     const METHOD_ID: u8;
 
-    fn generic_payload_size(&self) -> usize;
-    fn write_generic_payload(&self, buffer: &mut [u8]);
+    fn generic_payload_size(&self) -> usize {
+        0
+    }
+
+    fn write_generic_payload(&self, buffer: &mut [u8]) {
+        assert_eq!(buffer.len(), 0, "buffer size");
+    }
 }
 
 impl<T: GenericPayload> Payload for T {
@@ -54,18 +74,200 @@ impl<T: GenericPayload> Payload for T {
     }
 }
 
-pub struct GetUicFirmware;
+impl CommandHandler {
+    /// Send a generic command and return its response, failing if the pad reports an error
+    pub async fn generic<T: GenericPayload>(&self, command: &T) -> Result<T::Response, Error> {
+        self.generic_attempts(command, Self::RETRIES).await
+    }
+
+    /// Like [`Self::generic`], without resending the command when no ACK arrives. For
+    /// commands after which the pad may stop answering, like [`PowerOff`]
+    pub async fn generic_once<T: GenericPayload>(&self, command: &T) -> Result<T::Response, Error> {
+        self.generic_attempts(command, 1).await
+    }
+
+    async fn generic_attempts<T: GenericPayload>(&self, command: &T, attempts: usize) -> Result<T::Response, Error> {
+        let size = size_of::<CommandHeader>() + size_of::<GenericCommandHeader>() + size_of::<T::Response>();
+        ensure!(size <= self.mtu, ResponseSizeSnafu { size, mtu: self.mtu });
+
+        let response = self.exchange(command, attempts).await?;
+        let packet = CommandPacket::ref_from_bytes(&response).expect("already unpacked");
+        let incomplete = |reason: String| Error::Incomplete { reason };
+        // failed commands don't carry the response payload, so check the header first
+        let (header, payload) =
+            GenericCommandHeader::ref_from_prefix(&packet.payload).map_err(|x| incomplete(x.to_string()))?;
+        let code = header.error_code.get();
+        ensure!(code == 0, GenericFailedSnafu { code });
+        T::Response::read_from_bytes(payload).map_err(|x| incomplete(x.to_string()))
+    }
+
+    /// Turn the pad off, sending [`PowerOff`] only once
+    pub async fn power_off(&self) -> Result<(), Error> {
+        self.generic_once(&PowerOff).await
+    }
+}
+
+const SERVICE_SYSTEM: u8 = 0x01;
+const SERVICE_UIC: u8 = 0x05;
+
+// TODO: only GetUicConfig has been seen working. The other method ids are the best known ones and
+// still need confirming on a pad
+
+/// The factory configuration stored in the UIC, see [`UicConfig`](crate::cmd::uic::UicConfig)
+pub struct GetUicConfig;
 
-impl GenericPayload for GetUicFirmware {
+impl GenericPayload for GetUicConfig {
     type Response = [u8; 772];
-    const SERVICE_ID: u8 = 0x05;
+    const SERVICE_ID: u8 = SERVICE_UIC;
     const METHOD_ID: u8 = 0x06;
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, FromBytes, KnownLayout, Immutable, Unaligned)]
+pub struct FirmwareVersion {
+    version: big_endian::U32,
+}
+
+impl FirmwareVersion {
+    pub fn get(&self) -> u32 {
+        self.version.get()
+    }
+}
+
+/// Version of the pad's main firmware
+pub struct GetFirmwareVersion;
+
+impl GenericPayload for GetFirmwareVersion {
+    type Response = FirmwareVersion;
+    const SERVICE_ID: u8 = SERVICE_SYSTEM;
+    const METHOD_ID: u8 = 0x00;
+}
+
+/// Version of the firmware on the UIC, the microcontroller handling power and input
+pub struct GetUicVersion;
+
+impl GenericPayload for GetUicVersion {
+    type Response = FirmwareVersion;
+    const SERVICE_ID: u8 = SERVICE_UIC;
+    const METHOD_ID: u8 = 0x0d;
+}
+
+/// Backlight level, as in the pad's own settings
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromBytes, KnownLayout, Immutable, Unaligned)]
+pub struct LcdBrightness(u8);
+
+impl LcdBrightness {
+    pub const MIN: u8 = 1;
+    pub const MAX: u8 = 5;
+
+    pub fn level(&self) -> u8 {
+        self.0
+    }
+}
+
+pub struct GetLcdBrightness;
+
+impl GenericPayload for GetLcdBrightness {
+    type Response = LcdBrightness;
+    const SERVICE_ID: u8 = SERVICE_UIC;
+    const METHOD_ID: u8 = 0x0a;
+}
+
+/// Set the backlight level, clamped to [`LcdBrightness::MIN`]..=[`LcdBrightness::MAX`]
+pub struct SetLcdBrightness(pub u8);
+
+impl GenericPayload for SetLcdBrightness {
+    type Response = ();
+    const SERVICE_ID: u8 = SERVICE_UIC;
+    const METHOD_ID: u8 = 0x0b;
 
     fn generic_payload_size(&self) -> usize {
-        0
+        1
     }
 
     fn write_generic_payload(&self, buffer: &mut [u8]) {
-        assert_eq!(buffer.len(), 0, "buffer size");
+        buffer[0] = self.0.clamp(LcdBrightness::MIN, LcdBrightness::MAX);
+    }
+}
+
+/// Turn the pad off. It stops responding, so expect the command to time out, see
+/// [`CommandHandler::power_off`]
+pub struct PowerOff;
+
+impl GenericPayload for PowerOff {
+    type Response = ();
+    const SERVICE_ID: u8 = SERVICE_UIC;
+    const METHOD_ID: u8 = 0x0c;
+}
+
+/// Put the pad into standby, where it waits to be woken up by the console
+pub struct Standby;
+
+impl GenericPayload for Standby {
+    type Response = ();
+    const SERVICE_ID: u8 = SERVICE_UIC;
+    const METHOD_ID: u8 = 0x0e;
+}
+
+/// Read `N` bytes of the UIC's EEPROM, starting at `offset`. `N` has to fit a u16, and
+/// the response has to fit the command handler's receive buffer
+pub struct ReadUicEeprom<const N: usize> {
+    pub offset: u16,
+}
+
+impl<const N: usize> ReadUicEeprom<N> {
+    const LENGTH: u16 = {
+        assert!(N <= u16::MAX as usize, "EEPROM reads are limited to u16::MAX bytes");
+        N as u16
+    };
+}
+
+impl<const N: usize> GenericPayload for ReadUicEeprom<N> {
+    type Response = [u8; N];
+    const SERVICE_ID: u8 = SERVICE_UIC;
+    const METHOD_ID: u8 = 0x04;
+
+    fn generic_payload_size(&self) -> usize {
+        4
+    }
+
+    fn write_generic_payload(&self, buffer: &mut [u8]) {
+        buffer[..2].copy_from_slice(&self.offset.to_be_bytes());
+        buffer[2..].copy_from_slice(&Self::LENGTH.to_be_bytes());
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn eeprom_read_packet() {
+        let command = ReadUicEeprom::<0x300> { offset: 0x1234 };
+        let mut packet = vec![0u8; command.packet_size()];
+        command.write_packet(0x0102, &mut packet);
+        assert_eq!(
+            packet,
+            [
+                0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x02, 0x01, // command header, little endian
+                0x7e, 0x01, 0x00, 0x08, 0x00, 0x40, 0x05, 0x04, 0x00, 0x00, 0x00, 0x04, // generic header
+                0x12, 0x34, 0x03, 0x00, // offset and length
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_command_packet() {
+        let mut packet = vec![0u8; GetUicVersion.payload_size()];
+        GetUicVersion.write_payload(&mut packet);
+        assert_eq!(packet, [0x7e, 0x01, 0x00, 0x08, 0x00, 0x40, 0x05, 0x0d, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn brightness_clamped() {
+        let mut packet = vec![0u8; SetLcdBrightness(9).payload_size()];
+        SetLcdBrightness(9).write_payload(&mut packet);
+        assert_eq!(packet[12..], [LcdBrightness::MAX]);
+    }
+}
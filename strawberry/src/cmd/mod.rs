@@ -1,5 +1,7 @@
 use crate::cmd::data::{CommandHeader, CommandPacket, Payload};
 use crate::cmd::generic::GenericPayload;
+use crate::config::MIN_MTU;
+use crate::net;
 use snafu::{ensure, Report, ResultExt, Snafu};
 use std::process::Termination;
 use std::sync::atomic::{AtomicU16, Ordering};
@@ -12,11 +14,14 @@ use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
 
 pub mod data;
 pub mod generic;
+pub mod uic;
 
 pub struct CommandHandler {
     seq_id: AtomicU16,
     socket: Arc<UdpSocket>,
     broadcast: broadcast::Sender<Arc<[u8]>>,
+    /// Size of the receive buffer, no response can be longer
+    mtu: usize,
 }
 
 impl CommandHandler {
@@ -24,7 +29,13 @@ impl CommandHandler {
     const RETRIES: usize = 10;
 
     pub async fn new() -> Result<Self, Error> {
-        let socket = UdpSocket::bind("192.168.1.10:50023")
+        Self::with_mtu(net::host_mtu()).await
+    }
+
+    /// Use `mtu` to size the receive buffer, e.g. the streamer's [`Config::mtu`](crate::config::Config::mtu)
+    pub async fn with_mtu(mtu: usize) -> Result<Self, Error> {
+        ensure!(mtu >= MIN_MTU, MtuSnafu { mtu });
+        let socket = UdpSocket::bind((net::HOST_ADDR, 50023))
             .await
             .context(ConnectingSnafu)?;
         socket
@@ -39,7 +50,7 @@ impl CommandHandler {
         tokio::spawn(async move {
             let result: Report<Error> = (async {
                 loop {
-                    let mut buff = vec![0; 1800]; // TODO: introduce MTU constant
+                    let mut buff = vec![0; mtu];
                     let bytes = sock.recv(&mut buff).await.context(ReceiveSnafu)?;
                     buff.resize(bytes, 0);
                     let Some(broadcast) = bc.upgrade() else {
@@ -58,6 +69,7 @@ impl CommandHandler {
             seq_id: AtomicU16::new(0),
             socket,
             broadcast,
+            mtu,
         })
     }
 
@@ -111,6 +123,15 @@ impl CommandHandler {
     }
 
     pub async fn command<T: Payload>(&self, data: &T) -> Result<T::Response, Error> {
+        let response = self.exchange(data, Self::RETRIES).await?;
+        let response = CommandPacket::ref_from_bytes(&response).expect("already unpacked");
+        T::Response::read_from_bytes(&response.payload).map_err(|x| Error::Incomplete {
+            reason: x.to_string(),
+        })
+    }
+
+    /// Send `data` up to `attempts` times until it's acknowledged, and return the response packet
+    async fn exchange<T: Payload>(&self, data: &T, attempts: usize) -> Result<Arc<[u8]>, Error> {
         let seq_id = self.next_seq_id();
         let mut rcv = self.broadcast.subscribe();
 
@@ -124,7 +145,7 @@ impl CommandHandler {
                 res = self.recv_packet(&mut rcv, seq_id) => res?,
                 _ = tokio::time::sleep(Self::TIMEOUT) => {
                     retries += 1;
-                    ensure!(retries < Self::RETRIES, TimeoutSnafu);
+                    ensure!(retries < attempts, TimeoutSnafu);
                     continue;
                 }
             };
@@ -136,15 +157,13 @@ impl CommandHandler {
         }
 
         let response = self.recv_packet(&mut rcv, seq_id).await?;
-        let response = CommandPacket::ref_from_bytes(&response).expect("already unpacked");
+        let packet = CommandPacket::ref_from_bytes(&response).expect("already unpacked");
 
-        ensure!(response.header.packet_type == 2, ResponseExpectedSnafu);
+        ensure!(packet.header.packet_type == 2, ResponseExpectedSnafu);
         self.send_ack(seq_id, data)
             .await
             .context(SendSnafu)?;
-        T::Response::read_from_bytes(&response.payload).map_err(|x| Error::Incomplete {
-            reason: x.to_string(),
-        })
+        Ok(response)
     }
 }
 
@@ -166,4 +185,12 @@ pub enum Error {
     PayloadLength,
     /// Timeout
     Timeout,
+    #[snafu(display("generic command failed with error code {code:#06x}"))]
+    GenericFailed { code: u16 },
+    #[snafu(display("MTU {mtu} is below the minimum of {MIN_MTU}"))]
+    Mtu { mtu: usize },
+    #[snafu(display("a {size} byte response doesn't fit the {mtu} byte receive buffer"))]
+    ResponseSize { size: usize, mtu: usize },
+    /// invalid UIC config
+    UicConfig { source: uic::UicConfigError },
 }
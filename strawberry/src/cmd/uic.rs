@@ -0,0 +1,155 @@
+use crate::cmd::generic::GetUicConfig;
+use crate::cmd::{CommandHandler, Error, UicConfigSnafu};
+use crate::state::{AxisCalibration, InputConfig};
+use crate::touch::TouchCalibration;
+use snafu::{ResultExt, Snafu};
+use std::ops::Range;
+
+pub const SIZE: usize = 772;
+
+// TODO: the block offsets still need confirming against dumps from real pads
+/// Min, center and max of left x, left y, right x and right y, as u16 LE
+const STICKS: Range<usize> = 0x04..0x1c;
+/// Raw and screen coordinates of two reference points, as u16 LE
+const TOUCH: Range<usize> = 0x20..0x30;
+/// Accelerometer then gyroscope offsets, as i16 LE
+const SENSORS: Range<usize> = 0x34..0x40;
+const REGION: usize = 0x44;
+const LANGUAGE: usize = 0x45;
+/// UIC firmware version, u32 BE
+const FIRMWARE: Range<usize> = 0x48..0x4c;
+
+/// Factory configuration of the pad, as stored in the UIC's EEPROM
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UicConfig {
+    /// x and y axis of each stick
+    pub left_stick: [AxisCalibration; 2],
+    pub right_stick: [AxisCalibration; 2],
+    pub touch: TouchCalibration,
+    /// Raw readings of the sensors at rest
+    pub accel_offset: [i16; 3],
+    pub gyro_offset: [i16; 3],
+    pub region: u8,
+    pub language: u8,
+    pub firmware_version: u32,
+}
+
+impl UicConfig {
+    /// Decode the calibration blocks of `data`, failing if any block's CRC doesn't match
+    pub fn parse(data: &[u8; SIZE]) -> Result<Self, UicConfigError> {
+        let u16s = |block| {
+            let bytes = checked(data, block)?;
+            Ok(bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])))
+        };
+
+        let mut sticks = u16s(STICKS)?;
+        let mut axis = || AxisCalibration {
+            min: sticks.next().unwrap(),
+            center: sticks.next().unwrap(),
+            max: sticks.next().unwrap(),
+        };
+        let (left_stick, right_stick) = ([axis(), axis()], [axis(), axis()]);
+
+        let touch: Vec<_> = u16s(TOUCH)?.collect();
+        let sensors: Vec<_> = u16s(SENSORS)?.map(|value| value as i16).collect();
+
+        Ok(Self {
+            left_stick,
+            right_stick,
+            touch: TouchCalibration {
+                raw: [(touch[0], touch[1]), (touch[2], touch[3])],
+                screen: [(touch[4], touch[5]), (touch[6], touch[7])],
+            },
+            accel_offset: [sensors[0], sensors[1], sensors[2]],
+            gyro_offset: [sensors[3], sensors[4], sensors[5]],
+            region: data[REGION],
+            language: data[LANGUAGE],
+            firmware_version: u32::from_be_bytes(data[FIRMWARE].try_into().unwrap()),
+        })
+    }
+
+    /// Use the factory stick and touchscreen calibration in `config`, keeping its deadzones
+    pub fn apply(&self, config: &mut InputConfig) {
+        [config.left_stick.x, config.left_stick.y] = self.left_stick;
+        [config.right_stick.x, config.right_stick.y] = self.right_stick;
+        config.touch = self.touch;
+    }
+}
+
+/// `block` of `data`, if the CRC following it matches
+fn checked(data: &[u8], block: Range<usize>) -> Result<&[u8], UicConfigError> {
+    let stored = u16::from_le_bytes([data[block.end], data[block.end + 1]]);
+    let computed = crc16(&data[block.clone()]);
+    snafu::ensure!(
+        stored == computed,
+        ChecksumSnafu {
+            offset: block.start,
+            stored,
+            computed
+        }
+    );
+    Ok(&data[block])
+}
+
+/// CRC-16/CCITT-FALSE
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = (crc << 1) ^ (0x1021 & (crc >> 15).wrapping_neg());
+        }
+    }
+    crc
+}
+
+impl CommandHandler {
+    /// Read and parse the pad's factory configuration
+    pub async fn uic_config(&self) -> Result<UicConfig, Error> {
+        let data = self.generic(&GetUicConfig).await?;
+        UicConfig::parse(&data).context(UicConfigSnafu)
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum UicConfigError {
+    #[snafu(display("checksum mismatch in block at {offset:#x}: stored {stored:#06x}, computed {computed:#06x}"))]
+    Checksum { offset: usize, stored: u16, computed: u16 },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write(data: &mut [u8; SIZE], block: Range<usize>, values: &[u16]) {
+        for (bytes, value) in data[block.clone()].chunks_exact_mut(2).zip(values) {
+            bytes.copy_from_slice(&value.to_le_bytes());
+        }
+        let crc = crc16(&data[block.clone()]);
+        data[block.end..block.end + 2].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    // TODO: replace with a dump from a real pad, this only checks parsing against the guessed layout
+    #[test]
+    fn parse_and_validate() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+
+        let mut data = [0u8; SIZE];
+        write(&mut data, STICKS, &[900, 2048, 3200, 880, 2040, 3180, 910, 2050, 3210, 890, 2045, 3190]);
+        write(&mut data, TOUCH, &[200, 3800, 3900, 300, 50, 50, 800, 430]);
+        write(&mut data, SENSORS, &[12, (-8i16) as u16, 4096, 3, 1, (-2i16) as u16]);
+        data[REGION] = 2;
+
+        let config = UicConfig::parse(&data).unwrap();
+        assert_eq!(config.right_stick[1].center, 2045);
+        assert_eq!(config.touch.screen[1], (800, 430));
+        assert_eq!(config.accel_offset, [12, -8, 4096]);
+        assert_eq!(config.region, 2);
+
+        data[TOUCH.start] ^= 1;
+        assert!(matches!(
+            UicConfig::parse(&data),
+            Err(UicConfigError::Checksum { offset: 0x20, .. })
+        ));
+    }
+}
@@ -1,47 +1,309 @@
 use crate::data::InputData;
+use crate::event::{EventKind, EventTracker, InputEvent};
+use crate::input::link::LinkTracker;
+use crate::net;
+use crate::power::{PowerConfig, PowerState, PowerTracker};
+use crate::record::Recording;
 use snafu::{ResultExt, Snafu};
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
-use tokio::sync::watch;
-use tokio::sync::watch::error::{RecvError, SendError};
-use tokio::sync::watch::Ref;
+use tokio::sync::{broadcast, watch};
+use tokio::sync::watch::error::RecvError;
 use zerocopy::transmute;
-use crate::input::InputError::{RecvSocketClosed, SendSocketClosed};
 
 pub mod data;
+pub mod dsu;
+pub mod event;
+pub mod gesture;
+mod link;
+pub mod motion;
+pub mod pointer;
+pub mod power;
+pub mod record;
+pub mod remap;
+pub mod state;
+pub mod touch;
 
-pub struct InputReader {
-    recv: watch::Receiver<Result<InputData, Arc<InputError>>>,
+pub use link::InputStats;
+
+type InputState = Result<InputData, Arc<InputError>>;
+
+/// Receives gamepad input and shares it between any number of readers and event subscribers
+#[derive(Clone)]
+pub struct InputHub {
+    state: watch::Receiver<InputState>,
+    reports: broadcast::Sender<(Instant, InputData)>,
+    events: broadcast::Sender<InputEvent>,
+    link: Arc<Mutex<LinkTracker>>,
+    connected: watch::Receiver<bool>,
+    power: watch::Receiver<PowerState>,
 }
 
-impl InputReader {
+/// Sending half of an [`InputHub`], fed by the socket or a recording
+struct Publisher {
+    state: watch::Sender<InputState>,
+    reports: broadcast::Sender<(Instant, InputData)>,
+    events: broadcast::Sender<InputEvent>,
+    tracker: EventTracker,
+    pending: Vec<InputEvent>,
+    link: Arc<Mutex<LinkTracker>>,
+    connected: watch::Sender<bool>,
+    power_tracker: PowerTracker,
+    power: watch::Sender<PowerState>,
+}
+
+impl Publisher {
+    fn publish(&mut self, data: InputData, time: Instant) -> Result<(), InputError> {
+        self.link.lock().unwrap().report(data.seq_id.get(), time);
+        self.connected.send_if_modified(|connected| !std::mem::replace(connected, true));
+        let _ = self.reports.send((time, data));
+        self.tracker.update(&data, time, &mut self.pending);
+        let power = self.power_tracker.update(&data, time, &mut self.pending);
+        self.power.send_if_modified(|state| std::mem::replace(state, power) != power);
+        for event in self.pending.drain(..) {
+            let _ = self.events.send(event);
+        }
+        self.state.send_modify(|state| *state = Ok(data));
+        let unused = self.state.is_closed()
+            && self.reports.receiver_count() == 0
+            && self.events.receiver_count() == 0;
+        snafu::ensure!(!unused, SendSocketClosedSnafu);
+        Ok(())
+    }
+
+    fn disconnected(&self) {
+        self.link.lock().unwrap().disconnected();
+        self.connected.send_if_modified(|connected| std::mem::replace(connected, false));
+    }
+
+    fn fail(&self, error: InputError) {
+        let _ = self.state.send(Err(Arc::new(error)));
+    }
+}
+
+impl InputHub {
+    /// Reports and events a subscriber can fall behind by before it starts missing them
+    const CAPACITY: usize = 1024;
+    /// The pad counts as disconnected when no report arrives for this long
+    const DISCONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
     pub async fn new() -> Result<Self, InputError> {
-        let sock: UdpSocket = UdpSocket::bind(("192.168.1.10", 50022)).await.context(UdpSetupSnafu)?;
-        let (send, recv) = watch::channel(Ok(zerocopy::FromZeros::new_zeroed()));
+        Self::with_power_config(PowerConfig::default()).await
+    }
+
+    /// Like [`Self::new`], turning battery readings into [`Self::power`] with `power`
+    pub async fn with_power_config(power: PowerConfig) -> Result<Self, InputError> {
+        let sock: UdpSocket = UdpSocket::bind((net::HOST_ADDR, 50022)).await.context(UdpSetupSnafu)?;
+        let (hub, mut publisher) = Self::channels(power);
         tokio::task::spawn(async move {
-            if let Err(e) = (|| async {
+            let result: Result<(), InputError> = async {
+                let mut buff = [0u8; 256];
                 loop {
-                    let mut buff = [0u8; 128];
-                    let read_count = sock.recv(buff.as_mut_slice()).await.context(InputReadSnafu)?;
-                    snafu::ensure!(read_count == 128, IncompleteInputSnafu { bytes: read_count });
-                    if let Err(e) = send.send(Ok(transmute!(buff))) {
-                        return SendSocketClosedSnafu.fail::<()>();
-                    }
+                    let received = tokio::time::timeout(Self::DISCONNECT_TIMEOUT, sock.recv(&mut buff)).await;
+                    let Ok(received) = received else {
+                        publisher.disconnected();
+                        continue;
+                    };
+                    let read_count = received.context(InputReadSnafu)?;
+                    let Ok(report) = <[u8; 128]>::try_from(&buff[..read_count]) else {
+                        publisher.link.lock().unwrap().malformed();
+                        continue;
+                    };
+                    publisher.publish(transmute!(report), Instant::now())?;
                 }
-            })().await {
-                let _ = send.send(Err(Arc::new(e)));
+            }
+            .await;
+            if let Err(e) = result {
+                publisher.fail(e);
             }
         });
-        Ok(Self {
-            recv
-        })
+        Ok(hub)
+    }
+
+    /// Replay `recording` with its original timing, as if it came from the pad
+    pub fn playback(recording: Recording) -> Self {
+        let (hub, mut publisher) = Self::channels(PowerConfig::default());
+        tokio::task::spawn(async move {
+            let start = tokio::time::Instant::now();
+            for (offset, data) in recording {
+                tokio::time::sleep_until(start + offset).await;
+                if let Err(e) = publisher.publish(data, Instant::now()) {
+                    return publisher.fail(e);
+                }
+            }
+            publisher.fail(InputError::PlaybackEnded);
+        });
+        hub
+    }
+
+    fn channels(power_config: PowerConfig) -> (Self, Publisher) {
+        let (state_send, state) = watch::channel(Ok(zerocopy::FromZeros::new_zeroed()));
+        let (reports, _) = broadcast::channel(Self::CAPACITY);
+        let (events, _) = broadcast::channel(Self::CAPACITY);
+        let (connected_send, connected) = watch::channel(false);
+        let (power_send, power) = watch::channel(PowerState::default());
+        let link = Arc::default();
+        let publisher = Publisher {
+            state: state_send,
+            reports: reports.clone(),
+            events: events.clone(),
+            tracker: EventTracker::default(),
+            pending: Vec::new(),
+            link: Arc::clone(&link),
+            connected: connected_send,
+            power_tracker: PowerTracker::new(power_config),
+            power: power_send,
+        };
+        let hub = Self {
+            state,
+            reports,
+            events,
+            link,
+            connected,
+            power,
+        };
+        (hub, publisher)
+    }
+
+    /// Latest-state view of the input
+    pub fn reader(&self) -> InputReader {
+        InputReader {
+            recv: self.state.clone(),
+        }
+    }
+
+    /// Subscribe to every input report from now on, with the time it was received
+    pub fn reports(&self) -> broadcast::Receiver<(Instant, InputData)> {
+        self.reports.subscribe()
+    }
+
+    /// Subscribe to every button, stick and touch change from now on
+    pub fn events(&self) -> EventReceiver {
+        let recv = self.events.subscribe();
+        let mut tracker = EventTracker::default();
+        if let Ok(data) = &*self.state.borrow() {
+            tracker.update(data, Instant::now(), &mut Vec::new());
+        }
+        EventReceiver {
+            recv,
+            state: self.state.clone(),
+            tracker,
+            pending: VecDeque::new(),
+            resynced: None,
+        }
+    }
+
+    pub fn latest(&self) -> Result<InputData, Arc<InputError>> {
+        self.state.borrow().clone()
+    }
+
+    pub fn stats(&self) -> InputStats {
+        self.link.lock().unwrap().stats()
     }
 
+    /// Whether reports are arriving, changes when the pad disconnects or comes back
+    pub fn connected(&self) -> watch::Receiver<bool> {
+        self.connected.clone()
+    }
+
+    /// Smoothed battery and charging state, transitions are also sent as [`EventKind::Power`](crate::event::EventKind::Power) events
+    pub fn power(&self) -> watch::Receiver<PowerState> {
+        self.power.clone()
+    }
+}
+
+pub struct InputReader {
+    recv: watch::Receiver<InputState>,
+}
+
+impl InputReader {
+    /// Open a hub that only serves this reader, see [`InputHub`] to share it
+    pub async fn new() -> Result<Self, InputError> {
+        Ok(InputHub::new().await?.reader())
+    }
+
+    /// Wait for the next input report
     pub async fn read(&mut self) -> Result<InputData, Arc<InputError>> {
-        self.recv.changed().await.context(RecvSocketClosedSnafu).unwrap();
-        match &*self.recv.borrow_and_update() {
-            Ok(i) => Ok(*i),
-            Err(e) => Err(e.clone()),
+        if let Err(source) = self.recv.changed().await {
+            return Err(Arc::new(InputError::RecvSocketClosed { source }));
+        }
+        self.recv.borrow_and_update().clone()
+    }
+
+    /// Latest input report, without waiting
+    pub fn latest(&self) -> Result<InputData, Arc<InputError>> {
+        self.recv.borrow().clone()
+    }
+}
+
+/// Stream of input events, see [`InputHub::events`]
+pub struct EventReceiver {
+    recv: broadcast::Receiver<InputEvent>,
+    state: watch::Receiver<InputState>,
+    /// Follows the events handed out, to resync from after falling behind
+    tracker: EventTracker,
+    pending: VecDeque<InputEvent>,
+    /// Sequence id of the report the last resync caught up to
+    resynced: Option<u16>,
+}
+
+impl EventReceiver {
+    /// Wait for the next event. Returns `None` once the hub has stopped
+    pub async fn next(&mut self) -> Option<InputEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+            match self.recv.recv().await {
+                Ok(event) => self.receive(event),
+                Err(broadcast::error::RecvError::Lagged(missed)) => self.resync(missed),
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    fn receive(&mut self, event: InputEvent) {
+        // the resync already covered events of reports up to its own
+        if self
+            .resynced
+            .is_some_and(|seq_id| event.seq_id.wrapping_sub(seq_id) as i16 <= 0)
+        {
+            return;
+        }
+        self.tracker.apply(event.kind);
+        self.pending.push_back(event);
+    }
+
+    /// Replace the missed events with a [`EventKind::Lagged`] and the edges from
+    /// what was handed out last to the latest report
+    fn resync(&mut self, mut missed: u64) {
+        // what's still buffered is mostly older than the latest report, and would lag again
+        let mut buffered = Vec::new();
+        loop {
+            match self.recv.try_recv() {
+                Ok(event) => buffered.push(event),
+                Err(broadcast::error::TryRecvError::Lagged(skipped)) => missed += skipped,
+                Err(_) => break,
+            }
+        }
+        let time = Instant::now();
+        let latest = self.state.borrow().clone();
+        let seq_id = latest.as_ref().map_or(0, |data| data.seq_id.get());
+        self.pending.push_back(InputEvent {
+            seq_id,
+            time,
+            kind: EventKind::Lagged { missed },
+        });
+        if let Ok(data) = latest {
+            let mut events = Vec::new();
+            self.tracker.update(&data, time, &mut events);
+            self.pending.extend(events);
+            self.resynced = Some(seq_id);
+        }
+        for event in buffered {
+            self.receive(event);
         }
     }
 }
@@ -52,10 +314,42 @@ pub enum InputError {
     UdpSetup { source: std::io::Error },
     /// Reading input data
     InputRead { source: std::io::Error },
-    #[snafu(display("Read incomplete input packet ({bytes} != 128)"))]
-    IncompleteInput { bytes: usize },
     /// socket closed
     SendSocketClosed,
     /// socket closed
-    RecvSocketClosed { source: RecvError }
+    RecvSocketClosed { source: RecvError },
+    /// end of the recording
+    PlaybackEnded,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::Buttons;
+    use crate::event::Button;
+    use zerocopy::FromZeros;
+
+    #[tokio::test]
+    async fn lagged_events_resync() {
+        let (hub, mut publisher) = InputHub::channels(PowerConfig::default());
+        let mut events = hub.events();
+        let mut data = InputData::new_zeroed();
+        let mut publish = |seq_id: u16, buttons| {
+            data.seq_id = seq_id.into();
+            data.buttons = buttons;
+            publisher.publish(data, Instant::now()).unwrap();
+        };
+        // A toggles every report, B is pressed halfway and held
+        for seq_id in 1..=InputHub::CAPACITY as u16 * 2 {
+            let a = if seq_id % 2 == 1 { Buttons::A } else { Buttons::empty() };
+            let b = if seq_id >= 1000 { Buttons::B } else { Buttons::empty() };
+            publish(seq_id, a | b);
+        }
+
+        let mut next = async || events.next().await.map(|event| (event.seq_id, event.kind));
+        assert!(matches!(next().await, Some((2048, EventKind::Lagged { .. }))));
+        assert_eq!(next().await, Some((2048, EventKind::ButtonDown(Button::Main(Buttons::B)))));
+        publish(2049, Buttons::A | Buttons::B);
+        assert_eq!(next().await, Some((2049, EventKind::ButtonDown(Button::Main(Buttons::A)))));
+    }
 }
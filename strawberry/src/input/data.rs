@@ -1,21 +1,22 @@
 use bitflags::bitflags;
 use bitfld::layout;
 use zerocopy::{big_endian, little_endian};
-use zerocopy::FromBytes;
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone, FromBytes)]
+#[derive(Debug, Copy, Clone, FromBytes, IntoBytes, KnownLayout, Immutable)]
 pub struct InputData {
     pub seq_id: big_endian::U16,
     pub buttons: Buttons,
     pub power_status: PowerStatus,
     pub battery_charge: u8,
     pub left_stick_x: little_endian::U16,
-    pub left_stick_y: u16,
-    pub right_stick_x: u16,
-    pub right_stick_y: u16,
+    pub left_stick_y: little_endian::U16,
+    pub right_stick_x: little_endian::U16,
+    pub right_stick_y: little_endian::U16,
     pub audio_volume: u8,
     pub accelerometer: Accelerometer,
+    unk2: u8,
     pub gyro: Gyroscope,
     pub magnet: Magnet,
     pub touchscreen: Touchscreen,
@@ -25,8 +26,20 @@ pub struct InputData {
     pub fw_version_neg: u8,
 }
 
+impl InputData {
+    /// Raw (x, y) position of the left stick
+    pub fn left_stick(&self) -> (u16, u16) {
+        (self.left_stick_x.get(), self.left_stick_y.get())
+    }
+
+    /// Raw (x, y) position of the right stick
+    pub fn right_stick(&self) -> (u16, u16) {
+        (self.right_stick_x.get(), self.right_stick_y.get())
+    }
+}
+
 #[repr(transparent)]
-#[derive(Debug, Copy, Clone, FromBytes)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromBytes, IntoBytes, KnownLayout, Immutable)]
 pub struct Buttons(u16);
 
 bitflags! {
@@ -51,7 +64,7 @@ bitflags! {
 }
 
 #[repr(transparent)]
-#[derive(Debug, Copy, Clone, FromBytes)]
+#[derive(Debug, Copy, Clone, FromBytes, IntoBytes, KnownLayout, Immutable)]
 pub struct PowerStatus(u8);
 
 bitflags! {
@@ -65,7 +78,7 @@ bitflags! {
 }
 
 #[repr(transparent)]
-#[derive(Debug, Copy, Clone, FromBytes)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromBytes, IntoBytes, KnownLayout, Immutable)]
 pub struct ExtraButtons(u8);
 
 bitflags! {
@@ -79,7 +92,7 @@ bitflags! {
 }
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone, FromBytes)]
+#[derive(Debug, Copy, Clone, FromBytes, IntoBytes, KnownLayout, Immutable)]
 pub struct Accelerometer {
     pub z_accel: little_endian::I16,
     pub x_accel: little_endian::I16,
@@ -87,31 +100,105 @@ pub struct Accelerometer {
 }
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone, FromBytes)]
+#[derive(Debug, Copy, Clone, FromBytes, IntoBytes, KnownLayout, Immutable)]
 pub struct Gyroscope {
-    pub roll: i16,
-    pub yaw: i16,
-    pub pitch: i16,
-    pub pad: i16,
+    pub roll: little_endian::I16,
+    pub yaw: little_endian::I16,
+    pub pitch: little_endian::I16,
+    pub pad: little_endian::I16,
 }
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone, FromBytes)]
+#[derive(Debug, Copy, Clone, FromBytes, IntoBytes, KnownLayout, Immutable)]
 pub struct Magnet {
-    unknown: [u8; 6],
+    pub x: little_endian::I16,
+    pub y: little_endian::I16,
+    pub z: little_endian::I16,
 }
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone, FromBytes)]
+#[derive(Debug, Copy, Clone, FromBytes, IntoBytes, KnownLayout, Immutable)]
 pub struct Touchscreen {
     pub points: [[Coord; 2]; 10],
 }
 
+impl Touchscreen {
+    /// Raw position averaged over the samples, `None` while the screen isn't touched
+    pub fn position(&self) -> Option<(u16, u16)> {
+        let touched = self
+            .points
+            .iter()
+            .filter(|[x, y]| x.value() != 0 || y.value() != 0);
+        let (count, x, y) = touched.fold((0u32, 0u32, 0u32), |(count, x, y), [px, py]| {
+            (count + 1, x + px.value() as u32, y + py.value() as u32)
+        });
+        (count > 0).then(|| ((x / count) as u16, (y / count) as u16))
+    }
+
+    /// 12 bit pressure reading, spread over the extra bits of the first two samples
+    pub fn pressure(&self) -> u16 {
+        let [[x0, y0], [x1, y1]] = [self.points[0], self.points[1]];
+        x0.extra() | y0.extra() << 3 | x1.extra() << 6 | y1.extra() << 9
+    }
+}
+
 layout!({
-    #[derive(FromBytes)]
+    #[derive(FromBytes, IntoBytes, KnownLayout, Immutable)]
     pub struct Coord(u16);
     {
         let extra: Bits<14, 12>;
         let value: Bits<11, 0>;
     }
-});
\ No newline at end of file
+});
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::mem::offset_of;
+
+    /// A report laid out by hand at the documented offsets, not captured from a pad
+    fn report() -> [u8; 128] {
+        let mut report = [0u8; 128];
+        for (offset, bytes) in [
+            (0, &[0x01, 0x02][..]), // seq_id
+            (2, &[0x00, 0x80]), // A
+            (6, &[0x00, 0x04, 0xff, 0x0f, 0x00, 0x08, 0x10, 0x00]), // sticks
+            (15, &[0x00, 0x10, 0x00, 0x00, 0x00, 0xf0]), // accelerometer
+            (22, &[0x01, 0x00, 0xfe, 0xff, 0x00, 0x01, 0x00, 0x00]), // gyroscope
+            (30, &[0x10, 0x00, 0xf0, 0xff, 0x00, 0x01]), // magnet
+            (36, &[0x00, 0x08, 0x00, 0x04]), // first touch sample
+            (80, &[0x40]), // R3
+            (127, &[0xfe]), // fw_version_neg
+        ] {
+            report[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        report
+    }
+
+    #[test]
+    fn decode_report() {
+        let data = InputData::read_from_bytes(&report()).unwrap();
+        assert_eq!(data.seq_id.get(), 0x0102);
+        assert_eq!(data.buttons, Buttons::A);
+        assert_eq!(data.extra_buttons, ExtraButtons::R3);
+        assert_eq!(data.left_stick(), (0x0400, 0x0fff));
+        assert_eq!(data.right_stick(), (0x0800, 0x0010));
+        assert_eq!(
+            [data.accelerometer.z_accel.get(), data.accelerometer.x_accel.get(), data.accelerometer.y_accel.get()],
+            [4096, 0, -4096]
+        );
+        assert_eq!([data.gyro.roll.get(), data.gyro.yaw.get(), data.gyro.pitch.get()], [1, -2, 256]);
+        assert_eq!([data.magnet.x.get(), data.magnet.y.get(), data.magnet.z.get()], [16, -16, 256]);
+        assert_eq!(data.touchscreen.position(), Some((0x800, 0x400)));
+        assert_eq!(data.fw_version_neg, 0xfe);
+    }
+
+    #[test]
+    fn layout() {
+        assert_eq!(size_of::<InputData>(), 128);
+        assert_eq!(offset_of!(InputData, gyro), 22);
+        assert_eq!(offset_of!(InputData, magnet), 30);
+        assert_eq!(offset_of!(InputData, touchscreen), 36);
+    }
+}
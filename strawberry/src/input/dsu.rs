@@ -0,0 +1,388 @@
+use crate::data::InputData;
+use crate::motion::{MotionState, MotionTracker};
+use crate::power::{BatteryLevel, PowerState};
+use crate::state::{GamepadButtons, GamepadState, InputConfig};
+use crate::input::{InputError, InputHub};
+use snafu::{ResultExt, Snafu};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::select;
+
+const CLIENT_MAGIC: &[u8; 4] = b"DSUC";
+const SERVER_MAGIC: &[u8; 4] = b"DSUS";
+const PROTOCOL_VERSION: u16 = 1001;
+const HEADER_SIZE: usize = 16;
+
+const MESSAGE_VERSION: u32 = 0x100000;
+const MESSAGE_INFO: u32 = 0x100001;
+const MESSAGE_DATA: u32 = 0x100002;
+
+/// The pad is always served in the first of the four slots
+const SLOT: u8 = 0;
+const SLOT_COUNT: u8 = 4;
+
+/// Clients have to repeat their data request within this time to keep receiving data
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Request {
+    Version,
+    Info { slots: Vec<u8> },
+    Data { slot: Option<u8> },
+}
+
+struct Client {
+    last_request: Instant,
+    packet_number: u32,
+}
+
+/// Serves the gamepad's buttons, sticks, touch and motion to emulators over the
+/// cemuhook DSU protocol
+pub struct DsuServer {
+    socket: UdpSocket,
+    id: u32,
+    config: InputConfig,
+    motion: MotionTracker,
+    clients: HashMap<SocketAddr, Client>,
+    start: Instant,
+    latest: Option<(GamepadState, MotionState, PowerState)>,
+    /// Id of the current or last touch, clients tell touches apart by it
+    touch_id: u8,
+}
+
+impl DsuServer {
+    pub const DEFAULT_ADDRESS: &str = "127.0.0.1:26760";
+
+    pub async fn bind(address: impl ToSocketAddrs, config: InputConfig) -> Result<Self, DsuError> {
+        let socket = UdpSocket::bind(address).await.context(BindSnafu)?;
+        Ok(Self {
+            socket,
+            id: std::process::id(),
+            config,
+            motion: MotionTracker::default(),
+            clients: HashMap::new(),
+            start: Instant::now(),
+            latest: None,
+            touch_id: 0,
+        })
+    }
+
+    /// Serve input from `hub` until an error occurs
+    pub async fn run(mut self, hub: &InputHub) -> Result<(), DsuError> {
+        let mut reader = hub.reader();
+        let power = hub.power();
+        let mut buffer = [0u8; 128];
+        loop {
+            select! {
+                received = self.socket.recv_from(&mut buffer) => {
+                    let (length, address) = received.context(ReceiveSnafu)?;
+                    match parse_request(&buffer[..length]) {
+                        Some(request) => self.handle_request(request, address).await,
+                        None => eprintln!("ignoring invalid DSU packet from {address}"),
+                    }
+                }
+                data = reader.read() => {
+                    let data = data.context(InputSnafu)?;
+                    let power = *power.borrow();
+                    self.update(&data, power, Instant::now());
+                    self.send_data().await;
+                }
+            }
+        }
+    }
+
+    fn update(&mut self, data: &InputData, power: PowerState, time: Instant) {
+        let state = GamepadState::new(data, &self.config);
+        let motion = self.motion.update(data, time);
+        let touching = self.latest.as_ref().is_some_and(|(state, ..)| state.touch.is_some());
+        if state.touch.is_some() && !touching {
+            self.touch_id = self.touch_id.wrapping_add(1);
+        }
+        self.latest = Some((state, motion, power));
+    }
+
+    async fn handle_request(&mut self, request: Request, address: SocketAddr) {
+        match request {
+            Request::Version => {
+                let packet = self.packet(MESSAGE_VERSION, &PROTOCOL_VERSION.to_le_bytes());
+                self.send_to(&packet, address).await;
+            }
+            Request::Info { slots } => {
+                for slot in slots.into_iter().filter(|slot| *slot < SLOT_COUNT) {
+                    let mut payload = self.slot_info(slot).to_vec();
+                    payload.push(0);
+                    let packet = self.packet(MESSAGE_INFO, &payload);
+                    if !self.send_to(&packet, address).await {
+                        break;
+                    }
+                }
+            }
+            Request::Data { slot } => {
+                if slot.is_none_or(|slot| slot == SLOT) {
+                    let client = self.clients.entry(address).or_insert(Client {
+                        last_request: Instant::now(),
+                        packet_number: 0,
+                    });
+                    client.last_request = Instant::now();
+                }
+            }
+        }
+    }
+
+    /// Send `packet` to a client, dropping the client if that fails
+    async fn send_to(&mut self, packet: &[u8], address: SocketAddr) -> bool {
+        let result = self.socket.send_to(packet, address).await;
+        if let Err(e) = &result {
+            eprintln!("dropping DSU client {address}: {e}");
+            self.clients.remove(&address);
+        }
+        result.is_ok()
+    }
+
+    async fn send_data(&mut self) {
+        self.clients
+            .retain(|_, client| client.last_request.elapsed() < CLIENT_TIMEOUT);
+        let Some((state, motion, _)) = &self.latest else {
+            return;
+        };
+        let timestamp = self.start.elapsed().as_micros() as u64;
+        let mut payload = self.slot_info(SLOT).to_vec();
+        payload.push(1);
+        let number_offset = payload.len();
+        payload.extend([0; 4]);
+        controller_data(&mut payload, state, motion, self.touch_id, timestamp);
+        let mut failed = Vec::new();
+        for (address, client) in &mut self.clients {
+            payload[number_offset..number_offset + 4].copy_from_slice(&client.packet_number.to_le_bytes());
+            client.packet_number = client.packet_number.wrapping_add(1);
+            let packet = packet(self.id, MESSAGE_DATA, &payload);
+            if let Err(e) = self.socket.send_to(&packet, address).await {
+                eprintln!("dropping DSU client {address}: {e}");
+                failed.push(*address);
+            }
+        }
+        for address in failed {
+            self.clients.remove(&address);
+        }
+    }
+
+    /// Shared beginning of info and data responses
+    fn slot_info(&self, slot: u8) -> [u8; 11] {
+        const CONNECTED: u8 = 2;
+        const FULL_GYRO: u8 = 2;
+        let Some((_, _, power)) = self.latest.as_ref().filter(|_| slot == SLOT) else {
+            return [slot, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        };
+        let battery = match power {
+            PowerState { charging: true, .. } => 0xee,
+            PowerState { ac: true, .. } => 0xef,
+            PowerState { level: BatteryLevel::Critical, .. } => 0x01,
+            PowerState { level: BatteryLevel::Low, .. } => 0x02,
+            PowerState { percent, .. } if *percent < 50.0 => 0x03,
+            PowerState { percent, .. } if *percent < 90.0 => 0x04,
+            _ => 0x05,
+        };
+        [slot, CONNECTED, FULL_GYRO, 0, 0, 0, 0, 0, 0, 0, battery]
+    }
+
+    fn packet(&self, message: u32, payload: &[u8]) -> Vec<u8> {
+        packet(self.id, message, payload)
+    }
+}
+
+fn packet(id: u32, message: u32, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_SIZE + 4 + payload.len());
+    packet.extend(SERVER_MAGIC);
+    packet.extend(PROTOCOL_VERSION.to_le_bytes());
+    packet.extend(((4 + payload.len()) as u16).to_le_bytes());
+    packet.extend([0; 4]);
+    packet.extend(id.to_le_bytes());
+    packet.extend(message.to_le_bytes());
+    packet.extend(payload);
+    let crc = crc32(&packet);
+    packet[8..12].copy_from_slice(&crc.to_le_bytes());
+    packet
+}
+
+fn parse_request(packet: &[u8]) -> Option<Request> {
+    if packet.len() < HEADER_SIZE + 4 || &packet[..4] != CLIENT_MAGIC {
+        return None;
+    }
+    let length = u16::from_le_bytes([packet[6], packet[7]]) as usize;
+    if HEADER_SIZE + length != packet.len() {
+        return None;
+    }
+    let crc = u32::from_le_bytes(packet[8..12].try_into().unwrap());
+    let mut zeroed = packet.to_vec();
+    zeroed[8..12].fill(0);
+    if crc32(&zeroed) != crc {
+        return None;
+    }
+    let message = u32::from_le_bytes(packet[16..20].try_into().unwrap());
+    let body = &packet[20..];
+    match message {
+        MESSAGE_VERSION => Some(Request::Version),
+        MESSAGE_INFO => {
+            let count = i32::from_le_bytes(body.get(..4)?.try_into().unwrap());
+            let slots = body.get(4..4 + usize::try_from(count).ok()?)?;
+            Some(Request::Info {
+                slots: slots.to_vec(),
+            })
+        }
+        MESSAGE_DATA => {
+            const SLOT_BASED: u8 = 1;
+            let flags = *body.first()?;
+            Some(Request::Data {
+                slot: (flags == SLOT_BASED).then_some(*body.get(1)?),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Everything in a data response after the packet number
+fn controller_data(
+    payload: &mut Vec<u8>,
+    state: &GamepadState,
+    motion: &MotionState,
+    touch_id: u8,
+    timestamp: u64,
+) {
+    let bits = |mapping: &[(GamepadButtons, u8)]| {
+        mapping
+            .iter()
+            .filter(|(button, _)| state.pressed(*button))
+            .fold(0, |bits, (_, bit)| bits | bit)
+    };
+    let buttons1 = bits(&[
+        (GamepadButtons::LEFT, 0x80),
+        (GamepadButtons::DOWN, 0x40),
+        (GamepadButtons::RIGHT, 0x20),
+        (GamepadButtons::UP, 0x10),
+        (GamepadButtons::PLUS, 0x08),
+        (GamepadButtons::R3, 0x04),
+        (GamepadButtons::L3, 0x02),
+        (GamepadButtons::MINUS, 0x01),
+    ]);
+    let buttons2 = bits(&[
+        (GamepadButtons::Y, 0x80),
+        (GamepadButtons::B, 0x40),
+        (GamepadButtons::A, 0x20),
+        (GamepadButtons::X, 0x10),
+        (GamepadButtons::R, 0x08),
+        (GamepadButtons::L, 0x04),
+        (GamepadButtons::ZR, 0x02),
+        (GamepadButtons::ZL, 0x01),
+    ]);
+    let stick = |value: f32| ((value + 1.0) * 127.5).round().clamp(0.0, 255.0) as u8;
+    let analog = |button| if state.pressed(button) { 0xff } else { 0 };
+
+    payload.extend([
+        buttons1,
+        buttons2,
+        state.pressed(GamepadButtons::HOME) as u8,
+        state.touch.is_some() as u8,
+        stick(state.left_stick.x),
+        stick(state.left_stick.y),
+        stick(state.right_stick.x),
+        stick(state.right_stick.y),
+    ]);
+    payload.extend(
+        [
+            GamepadButtons::LEFT,
+            GamepadButtons::DOWN,
+            GamepadButtons::RIGHT,
+            GamepadButtons::UP,
+            GamepadButtons::Y,
+            GamepadButtons::B,
+            GamepadButtons::A,
+            GamepadButtons::X,
+            GamepadButtons::R,
+            GamepadButtons::L,
+            GamepadButtons::ZR,
+            GamepadButtons::ZL,
+        ]
+        .map(analog),
+    );
+
+    match state.touch {
+        Some(touch) => {
+            payload.extend([1, touch_id]);
+            payload.extend((touch.x.round() as u16).to_le_bytes());
+            payload.extend((touch.y.round() as u16).to_le_bytes());
+        }
+        None => payload.extend([0; 6]),
+    }
+    payload.extend([0; 6]);
+
+    payload.extend(timestamp.to_le_bytes());
+    for value in [
+        motion.accel.x,
+        motion.accel.y,
+        motion.accel.z,
+        motion.gyro.x,
+        motion.gyro.y,
+        motion.gyro.z,
+    ] {
+        payload.extend(value.to_le_bytes());
+    }
+}
+
+/// CRC-32 as used by zlib
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[derive(Debug, Snafu)]
+pub enum DsuError {
+    /// binding the DSU socket
+    Bind { source: std::io::Error },
+    /// receiving a DSU request
+    Receive { source: std::io::Error },
+    /// reading gamepad input
+    Input { source: Arc<InputError> },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zerocopy::FromZeros;
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn data_packet_size() {
+        let mut payload = vec![0; 16];
+        controller_data(&mut payload, &GamepadState::default(), &MotionState::default(), 0, 0);
+        assert_eq!(packet(0, MESSAGE_DATA, &payload).len(), 100);
+    }
+
+    #[tokio::test]
+    async fn touch_id_per_touch() {
+        let mut server = DsuServer::bind("127.0.0.1:0", InputConfig::default()).await.unwrap();
+        let mut data = InputData::new_zeroed();
+        let mut touch_ids = Vec::new();
+        for (seq_id, touched) in [false, true, true, true, false, true, true].into_iter().enumerate() {
+            data.seq_id = (seq_id as u16).into();
+            data.touchscreen.points[0][0] = match touched {
+                true => zerocopy::transmute!(0x0400u16 + seq_id as u16),
+                false => zerocopy::transmute!(0u16),
+            };
+            server.update(&data, PowerState::default(), Instant::now());
+            touch_ids.push(server.touch_id);
+        }
+        assert_eq!(touch_ids, [0, 1, 1, 1, 1, 2, 2]);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::data::{Buttons, ExtraButtons, InputData};
+use crate::power::PowerEvent;
+use std::time::Instant;
+
+/// Stick movement smaller than this, in raw units, isn't reported
+const STICK_THRESHOLD: u16 = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Button {
+    Main(Buttons),
+    Extra(ExtraButtons),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stick {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    ButtonDown(Button),
+    ButtonUp(Button),
+    /// Raw stick position
+    StickMoved { stick: Stick, x: u16, y: u16 },
+    /// Raw touchscreen position
+    TouchBegin { x: u16, y: u16 },
+    TouchMove { x: u16, y: u16 },
+    TouchEnd,
+    Power(PowerEvent),
+    /// The subscriber fell behind and missed this many events. The button, stick and
+    /// touch events that follow bring it up to the latest report, power changes are
+    /// only available from [`InputHub::power`](crate::InputHub::power)
+    Lagged { missed: u64 },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InputEvent {
+    /// Sequence id of the input report the event was decoded from
+    pub seq_id: u16,
+    /// When that report was received
+    pub time: Instant,
+    pub kind: EventKind,
+}
+
+/// Turns consecutive input reports into events
+#[derive(Default)]
+pub(crate) struct EventTracker {
+    buttons: Option<Buttons>,
+    extra_buttons: Option<ExtraButtons>,
+    /// last reported stick positions
+    sticks: [Option<(u16, u16)>; 2],
+    touch: Option<(u16, u16)>,
+}
+
+impl EventTracker {
+    /// Follow an event of another tracker, so [`Self::update`] continues from what it reported
+    pub fn apply(&mut self, kind: EventKind) {
+        match kind {
+            EventKind::ButtonDown(Button::Main(button)) => *self.buttons.get_or_insert(Buttons::empty()) |= button,
+            EventKind::ButtonUp(Button::Main(button)) => *self.buttons.get_or_insert(Buttons::empty()) -= button,
+            EventKind::ButtonDown(Button::Extra(button)) => {
+                *self.extra_buttons.get_or_insert(ExtraButtons::empty()) |= button
+            }
+            EventKind::ButtonUp(Button::Extra(button)) => {
+                *self.extra_buttons.get_or_insert(ExtraButtons::empty()) -= button
+            }
+            EventKind::StickMoved { stick, x, y } => self.sticks[stick as usize] = Some((x, y)),
+            EventKind::TouchBegin { x, y } | EventKind::TouchMove { x, y } => self.touch = Some((x, y)),
+            EventKind::TouchEnd => self.touch = None,
+            EventKind::Power(_) | EventKind::Lagged { .. } => {}
+        }
+    }
+
+    pub fn update(&mut self, data: &InputData, time: Instant, events: &mut Vec<InputEvent>) {
+        let mut push = |kind| {
+            events.push(InputEvent {
+                seq_id: data.seq_id.get(),
+                time,
+                kind,
+            })
+        };
+
+        let previous = self.buttons.replace(data.buttons).unwrap_or(Buttons::empty());
+        for (_, button) in (previous ^ data.buttons).iter_names() {
+            push(match data.buttons.contains(button) {
+                true => EventKind::ButtonDown(Button::Main(button)),
+                false => EventKind::ButtonUp(Button::Main(button)),
+            });
+        }
+
+        let previous = self
+            .extra_buttons
+            .replace(data.extra_buttons)
+            .unwrap_or(ExtraButtons::empty());
+        for (_, button) in (previous ^ data.extra_buttons).iter_names() {
+            push(match data.extra_buttons.contains(button) {
+                true => EventKind::ButtonDown(Button::Extra(button)),
+                false => EventKind::ButtonUp(Button::Extra(button)),
+            });
+        }
+
+        let sticks = [
+            (Stick::Left, data.left_stick()),
+            (Stick::Right, data.right_stick()),
+        ];
+        for (reported, (stick, (x, y))) in self.sticks.iter_mut().zip(sticks) {
+            let moved = reported.is_none_or(|(rx, ry)| {
+                rx.abs_diff(x) >= STICK_THRESHOLD || ry.abs_diff(y) >= STICK_THRESHOLD
+            });
+            if moved {
+                *reported = Some((x, y));
+                push(EventKind::StickMoved { stick, x, y });
+            }
+        }
+
+        let touch = data.touchscreen.position();
+        match (self.touch, touch) {
+            (None, Some((x, y))) => push(EventKind::TouchBegin { x, y }),
+            (Some(previous), Some((x, y))) if previous != (x, y) => {
+                push(EventKind::TouchMove { x, y })
+            }
+            (Some(_), None) => push(EventKind::TouchEnd),
+            _ => {}
+        }
+        self.touch = touch;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zerocopy::FromZeros;
+
+    fn kinds(tracker: &mut EventTracker, data: &InputData) -> Vec<EventKind> {
+        let mut events = Vec::new();
+        tracker.update(data, Instant::now(), &mut events);
+        events.into_iter().map(|event| event.kind).collect()
+    }
+
+    #[test]
+    fn button_edges() {
+        let mut tracker = EventTracker::default();
+        let mut data = InputData::new_zeroed();
+        kinds(&mut tracker, &data);
+
+        data.buttons = Buttons::A | Buttons::ZR;
+        data.extra_buttons = ExtraButtons::L3;
+        assert_eq!(
+            kinds(&mut tracker, &data),
+            [
+                EventKind::ButtonDown(Button::Main(Buttons::ZR)),
+                EventKind::ButtonDown(Button::Main(Buttons::A)),
+                EventKind::ButtonDown(Button::Extra(ExtraButtons::L3)),
+            ]
+        );
+        assert!(kinds(&mut tracker, &data).is_empty());
+
+        data.buttons = Buttons::ZR;
+        data.extra_buttons = ExtraButtons::empty();
+        assert_eq!(
+            kinds(&mut tracker, &data),
+            [
+                EventKind::ButtonUp(Button::Main(Buttons::A)),
+                EventKind::ButtonUp(Button::Extra(ExtraButtons::L3)),
+            ]
+        );
+    }
+}
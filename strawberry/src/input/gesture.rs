@@ -0,0 +1,251 @@
+use crate::touch::TouchPoint;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GestureConfig {
+    /// Movement in panel pixels beyond which a touch becomes a drag
+    pub drag_distance: f32,
+    /// Longest touch that still counts as a tap
+    pub tap_time: Duration,
+    /// Longest gap between two taps of a double tap
+    pub double_tap_time: Duration,
+    /// Holding still this long is a long press
+    pub long_press_time: Duration,
+    /// Drags released faster than this, in pixels per second, are swipes
+    pub swipe_velocity: f32,
+    /// Velocities are measured over this much of the latest movement
+    pub velocity_window: Duration,
+    /// The resistive panel briefly loses contact during light touches, gaps
+    /// shorter than this don't end the touch
+    pub release_time: Duration,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            drag_distance: 12.0,
+            tap_time: Duration::from_millis(250),
+            double_tap_time: Duration::from_millis(300),
+            long_press_time: Duration::from_millis(500),
+            swipe_velocity: 1000.0,
+            velocity_window: Duration::from_millis(80),
+            release_time: Duration::from_millis(30),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SwipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// Positions are in panel pixels and velocities in pixels per second
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Gesture {
+    /// Sent for every tap, including both taps of a double tap
+    Tap { x: f32, y: f32 },
+    /// Sent after the second tap
+    DoubleTap { x: f32, y: f32 },
+    LongPress { x: f32, y: f32 },
+    /// Where the drag started
+    DragStart { x: f32, y: f32 },
+    Drag { x: f32, y: f32, dx: f32, dy: f32, velocity: (f32, f32) },
+    DragEnd { x: f32, y: f32, velocity: (f32, f32) },
+    /// Sent after the `DragEnd` of a fast drag
+    Swipe { direction: SwipeDirection, velocity: (f32, f32) },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GestureEvent {
+    pub time: Instant,
+    pub gesture: Gesture,
+}
+
+struct Contact {
+    start: Instant,
+    origin: (f32, f32),
+    position: (f32, f32),
+    /// Recent positions for the velocity estimate
+    history: VecDeque<(Instant, (f32, f32))>,
+    dragging: bool,
+    long_pressed: bool,
+    lifted: Option<Instant>,
+}
+
+/// Recognizes gestures from the decoded touch position of consecutive reports.
+/// The panel only reports a single touch point, so there are no multi finger gestures
+pub struct GestureRecognizer {
+    config: GestureConfig,
+    contact: Option<Contact>,
+    last_tap: Option<(Instant, (f32, f32))>,
+}
+
+impl GestureRecognizer {
+    pub fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            contact: None,
+            last_tap: None,
+        }
+    }
+
+    /// Feed the touch point of a report, as decoded by [`TouchCalibration::decode`](crate::touch::TouchCalibration::decode)
+    pub fn update(&mut self, touch: Option<TouchPoint>, time: Instant, events: &mut Vec<GestureEvent>) {
+        let mut push = |gesture| events.push(GestureEvent { time, gesture });
+        let config = self.config;
+
+        let Some(contact) = &mut self.contact else {
+            if let Some(touch) = touch {
+                let position = (touch.x, touch.y);
+                self.contact = Some(Contact {
+                    start: time,
+                    origin: position,
+                    position,
+                    history: VecDeque::from([(time, position)]),
+                    dragging: false,
+                    long_pressed: false,
+                    lifted: None,
+                });
+            }
+            return;
+        };
+
+        let Some(touch) = touch else {
+            let lifted = *contact.lifted.get_or_insert(time);
+            if time.saturating_duration_since(lifted) < config.release_time {
+                return;
+            }
+            let contact = self.contact.take().unwrap();
+            let (x, y) = contact.position;
+            let velocity = velocity(&contact.history);
+            if contact.dragging {
+                push(Gesture::DragEnd { x, y, velocity });
+                if velocity.0.hypot(velocity.1) >= config.swipe_velocity {
+                    let direction = match velocity {
+                        (vx, vy) if vx.abs() >= vy.abs() && vx < 0.0 => SwipeDirection::Left,
+                        (vx, vy) if vx.abs() >= vy.abs() => SwipeDirection::Right,
+                        (_, vy) if vy < 0.0 => SwipeDirection::Up,
+                        _ => SwipeDirection::Down,
+                    };
+                    push(Gesture::Swipe { direction, velocity });
+                }
+            } else if !contact.long_pressed && lifted.saturating_duration_since(contact.start) <= config.tap_time {
+                push(Gesture::Tap { x, y });
+                let double = self.last_tap.take().is_some_and(|(tap, position)| {
+                    contact.start.saturating_duration_since(tap) <= config.double_tap_time
+                        && distance(position, (x, y)) < config.drag_distance
+                });
+                match double {
+                    true => push(Gesture::DoubleTap { x, y }),
+                    false => self.last_tap = Some((lifted, (x, y))),
+                }
+            }
+            return;
+        };
+
+        contact.lifted = None;
+        let previous = contact.position;
+        let position = (touch.x, touch.y);
+        contact.position = position;
+        contact.history.push_back((time, position));
+        while contact
+            .history
+            .front()
+            .is_some_and(|(sample, _)| time.saturating_duration_since(*sample) > config.velocity_window)
+        {
+            contact.history.pop_front();
+        }
+
+        if !contact.dragging && !contact.long_pressed && distance(contact.origin, position) >= config.drag_distance {
+            contact.dragging = true;
+            let (x, y) = contact.origin;
+            push(Gesture::DragStart { x, y });
+        }
+        if contact.dragging && position != previous {
+            push(Gesture::Drag {
+                x: position.0,
+                y: position.1,
+                dx: position.0 - previous.0,
+                dy: position.1 - previous.1,
+                velocity: velocity(&contact.history),
+            });
+        }
+        if !contact.dragging
+            && !contact.long_pressed
+            && time.saturating_duration_since(contact.start) >= config.long_press_time
+        {
+            contact.long_pressed = true;
+            let (x, y) = contact.position;
+            push(Gesture::LongPress { x, y });
+        }
+    }
+}
+
+impl Default for GestureRecognizer {
+    fn default() -> Self {
+        Self::new(GestureConfig::default())
+    }
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+fn velocity(history: &VecDeque<(Instant, (f32, f32))>) -> (f32, f32) {
+    let (Some((start, from)), Some((end, to))) = (history.front(), history.back()) else {
+        return (0.0, 0.0);
+    };
+    let dt = end.saturating_duration_since(*start).as_secs_f32();
+    if dt == 0.0 {
+        return (0.0, 0.0);
+    }
+    ((to.0 - from.0) / dt, (to.1 - from.1) / dt)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(recognizer: &mut GestureRecognizer, time: &mut Instant, touches: &[Option<(f32, f32)>]) -> Vec<Gesture> {
+        let mut events = Vec::new();
+        for touch in touches {
+            *time += Duration::from_millis(10);
+            let touch = touch.map(|(x, y)| TouchPoint { x, y, pressure: 0 });
+            recognizer.update(touch, *time, &mut events);
+        }
+        events.into_iter().map(|event| event.gesture).collect()
+    }
+
+    #[test]
+    fn taps_and_swipe() {
+        let mut recognizer = GestureRecognizer::default();
+        let mut time = Instant::now();
+        let tap = [Some((100.0, 100.0)), None, Some((101.0, 100.0)), None, None, None, None];
+        assert_eq!(
+            run(&mut recognizer, &mut time, &tap),
+            [Gesture::Tap { x: 101.0, y: 100.0 }]
+        );
+        assert_eq!(
+            run(&mut recognizer, &mut time, &tap),
+            [
+                Gesture::Tap { x: 101.0, y: 100.0 },
+                Gesture::DoubleTap { x: 101.0, y: 100.0 },
+            ]
+        );
+
+        let swipe: Vec<_> = (0..6)
+            .map(|step| Some((400.0 - step as f32 * 40.0, 200.0)))
+            .chain([None; 4])
+            .collect();
+        let gestures = run(&mut recognizer, &mut time, &swipe);
+        assert_eq!(gestures[0], Gesture::DragStart { x: 400.0, y: 200.0 });
+        assert!(matches!(
+            gestures.last(),
+            Some(Gesture::Swipe { direction: SwipeDirection::Left, .. })
+        ));
+    }
+}
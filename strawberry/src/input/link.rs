@@ -0,0 +1,119 @@
+use std::time::{Duration, Instant};
+
+/// Health of the input channel from the gamepad
+#[derive(Debug, Clone, Default)]
+pub struct InputStats {
+    /// Input reports received
+    pub packets: u64,
+    /// Reports received over the last second
+    pub packets_per_second: f64,
+    /// Reports skipped in the sequence ids
+    pub lost: u64,
+    /// Reports with a sequence id that was already seen or older than the latest one
+    pub duplicates: u64,
+    /// Packets on the input port that weren't a 128 byte report
+    pub malformed: u64,
+    /// Smoothed deviation of the time between reports from its average
+    pub jitter: Duration,
+    /// Whether reports are still arriving
+    pub connected: bool,
+}
+
+/// Tracks sequence ids and arrival times of input reports
+#[derive(Default)]
+pub(crate) struct LinkTracker {
+    stats: InputStats,
+    last_seq_id: Option<u16>,
+    last_time: Option<Instant>,
+    /// smoothed time between reports, in seconds
+    interval: f64,
+    jitter: f64,
+    window_start: Option<Instant>,
+    window_packets: u32,
+}
+
+impl LinkTracker {
+    /// Weight of the newest interval in the averages, as in RFC 3550
+    const SMOOTHING: f64 = 1.0 / 16.0;
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    pub fn report(&mut self, seq_id: u16, time: Instant) {
+        self.stats.packets += 1;
+        self.stats.connected = true;
+
+        match self.last_seq_id.map(|last| seq_id.wrapping_sub(last)) {
+            Some(0) => self.stats.duplicates += 1,
+            Some(step) if step >= 0x8000 => self.stats.duplicates += 1,
+            step => {
+                self.stats.lost += step.map_or(0, |step| step as u64 - 1);
+                self.last_seq_id = Some(seq_id);
+            }
+        }
+
+        if let Some(last) = self.last_time.replace(time) {
+            let interval = time.saturating_duration_since(last).as_secs_f64();
+            if self.interval == 0.0 {
+                self.interval = interval;
+            }
+            self.jitter += ((interval - self.interval).abs() - self.jitter) * Self::SMOOTHING;
+            self.interval += (interval - self.interval) * Self::SMOOTHING;
+            self.stats.jitter = Duration::from_secs_f64(self.jitter);
+        }
+
+        let start = *self.window_start.get_or_insert(time);
+        self.window_packets += 1;
+        let elapsed = time.saturating_duration_since(start);
+        if elapsed >= Self::WINDOW {
+            self.stats.packets_per_second = self.window_packets as f64 / elapsed.as_secs_f64();
+            self.window_start = Some(time);
+            self.window_packets = 0;
+        }
+    }
+
+    pub fn malformed(&mut self) {
+        self.stats.malformed += 1;
+    }
+
+    pub fn disconnected(&mut self) {
+        self.stats.connected = false;
+        self.stats.packets_per_second = 0.0;
+        // the pad starts a new sequence when it comes back
+        self.last_seq_id = None;
+        self.last_time = None;
+        self.window_start = None;
+        self.window_packets = 0;
+    }
+
+    pub fn stats(&self) -> InputStats {
+        self.stats.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sequence_gaps() {
+        let mut link = LinkTracker::default();
+        let time = Instant::now();
+        for seq_id in [0xfffe, 0xffff, 2, 2, 1, 3] {
+            link.report(seq_id, time);
+        }
+        let stats = link.stats();
+        assert_eq!(stats.packets, 6);
+        assert_eq!(stats.lost, 2);
+        assert_eq!(stats.duplicates, 2);
+
+        // neither an older nor a much newer sequence id is lost or duplicate after a reconnect
+        link.disconnected();
+        assert!(!link.stats().connected);
+        link.report(0x8100, time);
+        link.disconnected();
+        link.report(1, time);
+        link.report(2, time);
+        let stats = link.stats();
+        assert!(stats.connected);
+        assert_eq!((stats.lost, stats.duplicates), (2, 2));
+    }
+}
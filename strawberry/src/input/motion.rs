@@ -0,0 +1,279 @@
+use crate::data::InputData;
+use std::ops::{Add, Mul, Sub};
+use std::time::Instant;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Vector3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Vector3 {
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn length(self) -> f32 {
+        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+
+    pub fn cross(self, other: Self) -> Self {
+        Self::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    fn max_abs(self) -> f32 {
+        self.x.abs().max(self.y.abs()).max(self.z.abs())
+    }
+}
+
+impl Add for Vector3 {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl Sub for Vector3 {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+impl Mul<f32> for Vector3 {
+    type Output = Self;
+
+    fn mul(self, factor: f32) -> Self {
+        Self::new(self.x * factor, self.y * factor, self.z * factor)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Quaternion {
+    pub const IDENTITY: Self = Self {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    fn normalize(self) -> Self {
+        let length = (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+        Self {
+            w: self.w / length,
+            x: self.x / length,
+            y: self.y / length,
+            z: self.z / length,
+        }
+    }
+
+    /// Rotate `vector` from the world frame into the pad's frame
+    pub fn inverse_rotate(self, vector: Vector3) -> Vector3 {
+        let q = Vector3::new(-self.x, -self.y, -self.z);
+        let t = q.cross(vector) * 2.0;
+        vector + t * self.w + q.cross(t)
+    }
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MotionConfig {
+    /// Raw accelerometer reading of 1 g
+    pub accel_per_g: f32,
+    /// Raw gyroscope reading of 1 deg/s
+    pub gyro_per_dps: f32,
+    /// Gyro readings varying less than this (deg/s) count as holding still
+    pub stationary_gyro: f32,
+    /// Acceleration further than this from 1 g counts as moving
+    pub stationary_accel: f32,
+    /// Consecutive still samples averaged into a new gyro bias
+    pub calibration_samples: u32,
+    /// How strongly gravity pulls the orientation back, in 1/s
+    pub filter_gain: f32,
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self {
+            accel_per_g: 4096.0,
+            gyro_per_dps: 16.4,
+            stationary_gyro: 3.0,
+            stationary_accel: 0.05,
+            calibration_samples: 200,
+            filter_gain: 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct MotionState {
+    /// Acceleration in g
+    pub accel: Vector3,
+    /// Angular velocity in deg/s, with the gyro bias removed
+    pub gyro: Vector3,
+    /// Raw magnetometer reading. Its scale and axes are unknown, so it's passed through
+    /// unconverted and doesn't feed into the orientation
+    pub magnet: [i16; 3],
+    /// Orientation relative to lying still with gravity along +z
+    pub orientation: Quaternion,
+}
+
+/// Converts IMU readings to physical units and fuses them into an orientation
+/// with a Mahony style complementary filter
+pub struct MotionTracker {
+    config: MotionConfig,
+    bias: Vector3,
+    still_sum: Vector3,
+    still_count: u32,
+    orientation: Quaternion,
+    last: Option<Instant>,
+}
+
+impl MotionTracker {
+    pub fn new(config: MotionConfig) -> Self {
+        Self {
+            config,
+            bias: Vector3::default(),
+            still_sum: Vector3::default(),
+            still_count: 0,
+            orientation: Quaternion::IDENTITY,
+            last: None,
+        }
+    }
+
+    pub fn accel(&self, data: &InputData) -> Vector3 {
+        let accel = &data.accelerometer;
+        Vector3::new(
+            accel.x_accel.get() as f32,
+            accel.y_accel.get() as f32,
+            accel.z_accel.get() as f32,
+        ) * (1.0 / self.config.accel_per_g)
+    }
+
+    /// Angular velocity in deg/s, before bias correction
+    pub fn raw_gyro(&self, data: &InputData) -> Vector3 {
+        let gyro = &data.gyro;
+        Vector3::new(
+            gyro.pitch.get() as f32,
+            gyro.yaw.get() as f32,
+            gyro.roll.get() as f32,
+        ) * (1.0 / self.config.gyro_per_dps)
+    }
+
+    pub fn gyro_bias(&self) -> Vector3 {
+        self.bias
+    }
+
+    pub fn reset_orientation(&mut self) {
+        self.orientation = Quaternion::IDENTITY;
+    }
+
+    pub fn update(&mut self, data: &InputData, time: Instant) -> MotionState {
+        let accel = self.accel(data);
+        let raw_gyro = self.raw_gyro(data);
+        self.calibrate(accel, raw_gyro);
+        let gyro = raw_gyro - self.bias;
+
+        let dt = self
+            .last
+            .replace(time)
+            .map_or(0.0, |last| time.saturating_duration_since(last).as_secs_f32());
+        self.integrate(accel, gyro * (std::f32::consts::PI / 180.0), dt);
+
+        MotionState {
+            accel,
+            gyro,
+            magnet: [data.magnet.x.get(), data.magnet.y.get(), data.magnet.z.get()],
+            orientation: self.orientation,
+        }
+    }
+
+    /// Average the gyro over stretches of holding still into its bias
+    fn calibrate(&mut self, accel: Vector3, gyro: Vector3) {
+        let still = (accel.length() - 1.0).abs() < self.config.stationary_accel
+            && (self.still_count == 0
+                || (gyro - self.still_sum * (1.0 / self.still_count as f32)).max_abs()
+                    < self.config.stationary_gyro);
+        if !still {
+            self.still_sum = Vector3::default();
+            self.still_count = 0;
+            return;
+        }
+        self.still_sum = self.still_sum + gyro;
+        self.still_count += 1;
+        if self.still_count >= self.config.calibration_samples {
+            self.bias = self.still_sum * (1.0 / self.still_count as f32);
+            self.still_sum = Vector3::default();
+            self.still_count = 0;
+        }
+    }
+
+    fn integrate(&mut self, accel: Vector3, mut rate: Vector3, dt: f32) {
+        let length = accel.length();
+        // only trust the accelerometer for the gravity direction while it's near 1 g
+        if (0.5..1.5).contains(&length) {
+            let measured = accel * (1.0 / length);
+            let expected = self.orientation.inverse_rotate(Vector3::new(0.0, 0.0, 1.0));
+            rate = rate + measured.cross(expected) * self.config.filter_gain;
+        }
+        let q = self.orientation;
+        let half = rate * (0.5 * dt);
+        self.orientation = Quaternion {
+            w: q.w - q.x * half.x - q.y * half.y - q.z * half.z,
+            x: q.x + q.w * half.x + q.y * half.z - q.z * half.y,
+            y: q.y + q.w * half.y - q.x * half.z + q.z * half.x,
+            z: q.z + q.w * half.z + q.x * half.y - q.y * half.x,
+        }
+        .normalize();
+    }
+}
+
+impl Default for MotionTracker {
+    fn default() -> Self {
+        Self::new(MotionConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn converges_to_gravity_and_learns_bias() {
+        let mut tracker = MotionTracker::default();
+        tracker.orientation = Quaternion {
+            w: 0.9,
+            x: 0.3,
+            y: 0.0,
+            z: 0.0,
+        }
+        .normalize();
+        let (accel, gyro) = (Vector3::new(0.0, 0.0, 1.0), Vector3::new(2.0, -1.0, 0.5));
+        for _ in 0..2000 {
+            tracker.calibrate(accel, gyro);
+            tracker.integrate(accel, Vector3::default(), 0.005);
+        }
+        assert!((tracker.gyro_bias() - gyro).max_abs() < 1e-4);
+        let up = tracker.orientation.inverse_rotate(Vector3::new(0.0, 0.0, 1.0));
+        assert!((up - accel).max_abs() < 1e-3, "{up:?}");
+    }
+}
@@ -0,0 +1,198 @@
+use crate::input::InputReader;
+use crate::motion::{MotionState, MotionTracker};
+use crate::state::{GamepadButtons, GamepadState, InputConfig};
+use std::time::Instant;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ClutchMode {
+    /// The pointer only moves while the clutch is held
+    HoldToMove,
+    /// The pointer stops while the clutch is held, to reposition the pad
+    HoldToPause,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PointerConfig {
+    /// Pixels per degree of rotation at low speeds
+    pub sensitivity: f32,
+    /// Extra gain per 100 deg/s of rotation speed, so quick flicks cover the screen
+    /// while slow movement stays precise
+    pub acceleration: f32,
+    /// Rotation slower than this, in deg/s, is hand tremor and sensor noise
+    pub min_rate: f32,
+    /// Yaw moves the pointer horizontally and pitch vertically, these flip either
+    pub invert_x: bool,
+    pub invert_y: bool,
+    /// Empty to have the pointer always move
+    pub clutch: GamepadButtons,
+    pub clutch_mode: ClutchMode,
+    /// Pressing these sends [`PointerEvent::Recenter`]
+    pub recenter: GamepadButtons,
+}
+
+impl Default for PointerConfig {
+    fn default() -> Self {
+        Self {
+            sensitivity: 15.0,
+            acceleration: 1.0,
+            min_rate: 1.0,
+            invert_x: false,
+            invert_y: false,
+            clutch: GamepadButtons::empty(),
+            clutch_mode: ClutchMode::HoldToMove,
+            recenter: GamepadButtons::R3,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PointerEvent {
+    /// Relative motion in pixels
+    Move { dx: i32, dy: i32 },
+    /// The pointer should jump back to the center of the screen
+    Recenter,
+}
+
+/// Turns gyro yaw and pitch into relative pointer motion
+pub struct GyroPointer {
+    config: PointerConfig,
+    last: Option<Instant>,
+    /// Fractions of a pixel carried over to the next update
+    remainder: (f32, f32),
+    recenter_held: bool,
+}
+
+impl GyroPointer {
+    pub fn new(config: PointerConfig) -> Self {
+        Self {
+            config,
+            last: None,
+            remainder: (0.0, 0.0),
+            recenter_held: false,
+        }
+    }
+
+    fn active(&self, state: &GamepadState) -> bool {
+        if self.config.clutch.is_empty() {
+            return true;
+        }
+        let held = state.pressed(self.config.clutch);
+        match self.config.clutch_mode {
+            ClutchMode::HoldToMove => held,
+            ClutchMode::HoldToPause => !held,
+        }
+    }
+
+    fn axis(&self, rate: f32, dt: f32) -> f32 {
+        let speed = rate.abs();
+        if speed < self.config.min_rate {
+            return 0.0;
+        }
+        let gain = self.config.sensitivity * (1.0 + self.config.acceleration * speed / 100.0);
+        rate * dt * gain
+    }
+
+    pub fn update(&mut self, state: &GamepadState, motion: &MotionState, time: Instant, events: &mut Vec<PointerEvent>) {
+        let dt = self
+            .last
+            .replace(time)
+            .map_or(0.0, |last| time.saturating_duration_since(last).as_secs_f32());
+
+        let recenter = !self.config.recenter.is_empty() && state.pressed(self.config.recenter);
+        if recenter && !self.recenter_held {
+            self.remainder = (0.0, 0.0);
+            events.push(PointerEvent::Recenter);
+        }
+        self.recenter_held = recenter;
+
+        if !self.active(state) {
+            self.remainder = (0.0, 0.0);
+            return;
+        }
+        let sign = |invert| if invert { -1.0 } else { 1.0 };
+        // turning left (positive yaw) and tilting back (positive pitch) move left and up
+        let x = self.remainder.0 - self.axis(motion.gyro.y, dt) * sign(self.config.invert_x);
+        let y = self.remainder.1 - self.axis(motion.gyro.x, dt) * sign(self.config.invert_y);
+        let (dx, dy) = (x.trunc(), y.trunc());
+        self.remainder = (x - dx, y - dy);
+        if dx != 0.0 || dy != 0.0 {
+            events.push(PointerEvent::Move {
+                dx: dx as i32,
+                dy: dy as i32,
+            });
+        }
+    }
+
+    /// Run the pointer on reports from `reader` in a task, until the receiver is
+    /// dropped or the input fails
+    pub fn stream(mut self, mut reader: InputReader, input: InputConfig) -> mpsc::Receiver<PointerEvent> {
+        let (send, receive) = mpsc::channel(64);
+        tokio::spawn(async move {
+            let mut motion = MotionTracker::default();
+            let mut events = Vec::new();
+            while let Ok(data) = reader.read().await {
+                let time = Instant::now();
+                let state = GamepadState::new(&data, &input);
+                let motion = motion.update(&data, time);
+                self.update(&state, &motion, time, &mut events);
+                for event in events.drain(..) {
+                    if send.send(event).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        receive
+    }
+}
+
+impl Default for GyroPointer {
+    fn default() -> Self {
+        Self::new(PointerConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::motion::Vector3;
+    use std::time::Duration;
+
+    #[test]
+    fn clutch_and_subpixel_motion() {
+        let mut pointer = GyroPointer::new(PointerConfig {
+            sensitivity: 4.0,
+            acceleration: 0.0,
+            clutch: GamepadButtons::ZL,
+            ..Default::default()
+        });
+        let motion = MotionState {
+            gyro: Vector3::new(0.0, -8.0, 0.0),
+            ..Default::default()
+        };
+        let mut state = GamepadState::default();
+        let mut events = Vec::new();
+        let mut time = Instant::now();
+        for _ in 0..10 {
+            time += Duration::from_micros(15625);
+            pointer.update(&state, &motion, time, &mut events);
+        }
+        assert!(events.is_empty());
+
+        // 8 deg/s at 4 px/deg for 1/64 s is half a pixel per update
+        state.buttons = GamepadButtons::ZL | GamepadButtons::R3;
+        for _ in 0..4 {
+            time += Duration::from_micros(15625);
+            pointer.update(&state, &motion, time, &mut events);
+        }
+        assert_eq!(
+            events,
+            [
+                PointerEvent::Recenter,
+                PointerEvent::Move { dx: 1, dy: 0 },
+                PointerEvent::Move { dx: 1, dy: 0 },
+            ]
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use crate::data::{InputData, PowerStatus};
+use crate::event::{EventKind, InputEvent};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PowerConfig {
+    /// Raw `battery_charge` readings of an empty and a full battery
+    pub empty: u8,
+    pub full: u8,
+    /// Time constant of the smoothing applied to the percentage, the raw reading is noisy
+    pub smoothing: Duration,
+    /// Percentages below which the battery counts as low and critical
+    pub low: f32,
+    pub critical: f32,
+    /// How far the percentage has to rise above a threshold to leave that level again
+    pub hysteresis: f32,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            empty: 0,
+            full: 0xff,
+            smoothing: Duration::from_secs(10),
+            low: 15.0,
+            critical: 5.0,
+            hysteresis: 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BatteryLevel {
+    Critical,
+    Low,
+    #[default]
+    Normal,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct PowerState {
+    /// Smoothed charge in percent
+    pub percent: f32,
+    pub level: BatteryLevel,
+    pub charging: bool,
+    pub ac: bool,
+    pub power_button: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PowerEvent {
+    AcConnected,
+    AcDisconnected,
+    ChargingStarted,
+    ChargingStopped,
+    LevelChanged(BatteryLevel),
+    PowerButtonDown,
+    PowerButtonUp { held: Duration },
+}
+
+/// Turns the power status and battery charge of input reports into a smoothed
+/// battery state and power events
+pub struct PowerTracker {
+    config: PowerConfig,
+    state: Option<PowerState>,
+    last: Option<Instant>,
+    button_down: Option<Instant>,
+}
+
+impl PowerTracker {
+    pub fn new(config: PowerConfig) -> Self {
+        Self {
+            config,
+            state: None,
+            last: None,
+            button_down: None,
+        }
+    }
+
+    pub fn state(&self) -> PowerState {
+        self.state.unwrap_or_default()
+    }
+
+    fn raw_percent(&self, charge: u8) -> f32 {
+        let span = self.config.full as f32 - self.config.empty as f32;
+        if span <= 0.0 {
+            return 0.0;
+        }
+        ((charge as f32 - self.config.empty as f32) / span * 100.0).clamp(0.0, 100.0)
+    }
+
+    /// Thresholds are raised by the hysteresis while at or below them, so the
+    /// level only climbs once the percentage is clearly above
+    fn level(&self, percent: f32, previous: BatteryLevel) -> BatteryLevel {
+        let threshold = |threshold, level| match previous <= level {
+            true => threshold + self.config.hysteresis,
+            false => threshold,
+        };
+        if percent < threshold(self.config.critical, BatteryLevel::Critical) {
+            BatteryLevel::Critical
+        } else if percent < threshold(self.config.low, BatteryLevel::Low) {
+            BatteryLevel::Low
+        } else {
+            BatteryLevel::Normal
+        }
+    }
+
+    pub fn update(&mut self, data: &InputData, time: Instant, events: &mut Vec<InputEvent>) -> PowerState {
+        let mut push = |event| {
+            events.push(InputEvent {
+                seq_id: data.seq_id.get(),
+                time,
+                kind: EventKind::Power(event),
+            })
+        };
+        let status = data.power_status;
+        let raw = self.raw_percent(data.battery_charge);
+        let ac = status.contains(PowerStatus::AC_PLUGGED_IN);
+        let charging = status.contains(PowerStatus::CHARGING);
+        let power_button = status.contains(PowerStatus::POWER_BUTTON_PRESSED);
+
+        let dt = self
+            .last
+            .replace(time)
+            .map_or(0.0, |last| time.saturating_duration_since(last).as_secs_f32());
+        let state = match self.state {
+            // the reading jumps when the charger is plugged in or out, so start over then
+            Some(previous) if previous.ac == ac => {
+                let weight = (dt / self.config.smoothing.as_secs_f32()).min(1.0);
+                let percent = previous.percent + (raw - previous.percent) * weight;
+                PowerState {
+                    percent,
+                    level: self.level(percent, previous.level),
+                    charging,
+                    ac,
+                    power_button,
+                }
+            }
+            previous => PowerState {
+                percent: raw,
+                level: self.level(raw, previous.map_or(BatteryLevel::Normal, |state| state.level)),
+                charging,
+                ac,
+                power_button,
+            },
+        };
+
+        let previous = self.state.replace(state).unwrap_or_default();
+        if state.ac != previous.ac {
+            push(match state.ac {
+                true => PowerEvent::AcConnected,
+                false => PowerEvent::AcDisconnected,
+            });
+        }
+        if state.charging != previous.charging {
+            push(match state.charging {
+                true => PowerEvent::ChargingStarted,
+                false => PowerEvent::ChargingStopped,
+            });
+        }
+        if state.level != previous.level {
+            push(PowerEvent::LevelChanged(state.level));
+        }
+        match (self.button_down, state.power_button) {
+            (None, true) => {
+                self.button_down = Some(time);
+                push(PowerEvent::PowerButtonDown);
+            }
+            (Some(down), false) => {
+                self.button_down = None;
+                push(PowerEvent::PowerButtonUp {
+                    held: time.saturating_duration_since(down),
+                });
+            }
+            _ => {}
+        }
+        state
+    }
+}
+
+impl Default for PowerTracker {
+    fn default() -> Self {
+        Self::new(PowerConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zerocopy::FromZeros;
+
+    #[test]
+    fn thresholds_and_button() {
+        let mut tracker = PowerTracker::new(PowerConfig {
+            empty: 0,
+            full: 100,
+            smoothing: Duration::from_millis(1),
+            ..Default::default()
+        });
+        let mut data = InputData::new_zeroed();
+        let mut time = Instant::now();
+        let mut kinds = |charge, status| {
+            data.battery_charge = charge;
+            data.power_status = status;
+            time += Duration::from_millis(10);
+            let mut events = Vec::new();
+            tracker.update(&data, time, &mut events);
+            events.into_iter().map(|event| event.kind).collect::<Vec<_>>()
+        };
+
+        assert!(kinds(50, PowerStatus::empty()).is_empty());
+        assert_eq!(
+            kinds(10, PowerStatus::empty()),
+            [EventKind::Power(PowerEvent::LevelChanged(BatteryLevel::Low))]
+        );
+        // within the hysteresis above the low threshold
+        assert!(kinds(16, PowerStatus::empty()).is_empty());
+        assert_eq!(
+            kinds(20, PowerStatus::POWER_BUTTON_PRESSED),
+            [
+                EventKind::Power(PowerEvent::LevelChanged(BatteryLevel::Normal)),
+                EventKind::Power(PowerEvent::PowerButtonDown),
+            ]
+        );
+        assert_eq!(
+            kinds(20, PowerStatus::AC_PLUGGED_IN | PowerStatus::CHARGING),
+            [
+                EventKind::Power(PowerEvent::AcConnected),
+                EventKind::Power(PowerEvent::ChargingStarted),
+                EventKind::Power(PowerEvent::PowerButtonUp {
+                    held: Duration::from_millis(10)
+                }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use crate::data::InputData;
+use snafu::{ensure, ResultExt, Snafu};
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+use zerocopy::{transmute, IntoBytes};
+
+/// Recordings start with this, followed by a version byte and then records of a
+/// u64 LE offset in µs and the raw 128 byte input report
+const MAGIC: &[u8; 4] = b"SBIR";
+const VERSION: u8 = 1;
+
+/// Input reports with their offset from the start of the recording
+pub type Recording = Vec<(Duration, InputData)>;
+
+pub struct Recorder<W: Write> {
+    writer: W,
+    start: Option<Instant>,
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(mut writer: W) -> Result<Self, RecordError> {
+        writer.write_all(MAGIC).context(IoSnafu)?;
+        writer.write_all(&[VERSION]).context(IoSnafu)?;
+        Ok(Self {
+            writer,
+            start: None,
+        })
+    }
+
+    /// Append `data`, received at `time`. Timing is kept relative to the first report
+    pub fn record(&mut self, time: Instant, data: &InputData) -> Result<(), RecordError> {
+        let start = *self.start.get_or_insert(time);
+        let offset = time.saturating_duration_since(start).as_micros() as u64;
+        self.writer.write_all(&offset.to_le_bytes()).context(IoSnafu)?;
+        self.writer.write_all(data.as_bytes()).context(IoSnafu)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+pub fn read_recording(mut reader: impl Read) -> Result<Recording, RecordError> {
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header).context(IoSnafu)?;
+    ensure!(&header[..4] == MAGIC, MagicSnafu);
+    ensure!(header[4] == VERSION, VersionSnafu { version: header[4] });
+
+    let mut recording = Vec::new();
+    loop {
+        let mut offset = [0u8; 8];
+        match reader.read_exact(&mut offset) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(recording),
+            Err(e) => return Err(e).context(IoSnafu),
+        }
+        let mut report = [0u8; 128];
+        reader.read_exact(&mut report).context(IoSnafu)?;
+        recording.push((
+            Duration::from_micros(u64::from_le_bytes(offset)),
+            transmute!(report),
+        ));
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum RecordError {
+    /// reading or writing the recording
+    Io { source: std::io::Error },
+    /// not an input recording
+    Magic,
+    #[snafu(display("unsupported recording version {version}"))]
+    Version { version: u8 },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::Buttons;
+    use zerocopy::FromZeros;
+
+    #[test]
+    fn round_trip() {
+        let start = Instant::now();
+        let mut data = InputData::new_zeroed();
+        let mut recorder = Recorder::new(Vec::new()).unwrap();
+        recorder.record(start, &data).unwrap();
+        data.buttons = Buttons::A;
+        recorder.record(start + Duration::from_millis(5), &data).unwrap();
+
+        let recording = read_recording(recorder.into_inner().as_slice()).unwrap();
+        assert_eq!(recording.len(), 2);
+        assert_eq!(recording[1].0, Duration::from_millis(5));
+        assert_eq!(recording[1].1.buttons, Buttons::A);
+        assert_eq!(recording[1].1.as_bytes(), data.as_bytes());
+    }
+}
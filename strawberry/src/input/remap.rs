@@ -0,0 +1,362 @@
+use crate::state::{GamepadButtons, GamepadState, StickPosition};
+use serde::Deserialize;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfilesFile {
+    default: Option<String>,
+    #[serde(default)]
+    profiles: HashMap<String, ProfileFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileFile {
+    #[serde(default)]
+    buttons: HashMap<String, String>,
+    #[serde(default)]
+    chords: Vec<ChordFile>,
+    #[serde(default)]
+    turbo: Vec<String>,
+    turbo_rate: Option<f32>,
+    #[serde(default)]
+    left_stick: StickTransform,
+    #[serde(default)]
+    right_stick: StickTransform,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChordFile {
+    buttons: Vec<String>,
+    name: String,
+    profile: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StickTransform {
+    pub invert_x: bool,
+    pub invert_y: bool,
+    /// Deflection is multiplied by this, then limited to full deflection
+    pub sensitivity: f32,
+}
+
+impl StickTransform {
+    pub fn apply(&self, stick: StickPosition) -> StickPosition {
+        let sign = |invert| if invert { -1.0 } else { 1.0 };
+        let (x, y) = (
+            stick.x * sign(self.invert_x) * self.sensitivity,
+            stick.y * sign(self.invert_y) * self.sensitivity,
+        );
+        let magnitude = x.hypot(y);
+        let scale = if magnitude > 1.0 { 1.0 / magnitude } else { 1.0 };
+        StickPosition {
+            x: x * scale,
+            y: y * scale,
+        }
+    }
+}
+
+impl Default for StickTransform {
+    fn default() -> Self {
+        Self {
+            invert_x: false,
+            invert_y: false,
+            sensitivity: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chord {
+    pub buttons: GamepadButtons,
+    /// Reported in [`RemapEvent::Chord`]
+    pub name: String,
+    /// Profile to switch to when the chord is pressed
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// Pairs of a physical button and the buttons it presses instead, unlisted buttons press themselves
+    pub buttons: Vec<(GamepadButtons, GamepadButtons)>,
+    pub chords: Vec<Chord>,
+    /// Buttons, after remapping, that repeat while held
+    pub turbo: GamepadButtons,
+    /// Presses per second of turbo buttons
+    pub turbo_rate: f32,
+    pub left_stick: StickTransform,
+    pub right_stick: StickTransform,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            buttons: Vec::new(),
+            chords: Vec::new(),
+            turbo: GamepadButtons::empty(),
+            turbo_rate: 10.0,
+            left_stick: StickTransform::default(),
+            right_stick: StickTransform::default(),
+        }
+    }
+}
+
+fn button(name: &str) -> Result<GamepadButtons, RemapError> {
+    match name {
+        "" => Ok(GamepadButtons::empty()),
+        name => GamepadButtons::from_name(name).context(UnknownButtonSnafu { name }),
+    }
+}
+
+fn buttons(names: &[String]) -> Result<GamepadButtons, RemapError> {
+    names
+        .iter()
+        .try_fold(GamepadButtons::empty(), |buttons, name| Ok(buttons | button(name)?))
+}
+
+impl TryFrom<ProfileFile> for Profile {
+    type Error = RemapError;
+
+    fn try_from(file: ProfileFile) -> Result<Self, RemapError> {
+        let defaults = Profile::default();
+        Ok(Self {
+            buttons: file
+                .buttons
+                .iter()
+                .map(|(from, to)| Ok((button(from)?, button(to)?)))
+                .collect::<Result<_, RemapError>>()?,
+            chords: file
+                .chords
+                .into_iter()
+                .map(|chord| {
+                    Ok(Chord {
+                        buttons: buttons(&chord.buttons)?,
+                        name: chord.name,
+                        profile: chord.profile,
+                    })
+                })
+                .collect::<Result<_, RemapError>>()?,
+            turbo: buttons(&file.turbo)?,
+            turbo_rate: file.turbo_rate.unwrap_or(defaults.turbo_rate),
+            left_stick: file.left_stick,
+            right_stick: file.right_stick,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RemapEvent {
+    Chord(String),
+    ProfileChanged(String),
+}
+
+/// Applies one of a set of named profiles to the gamepad state
+pub struct Remapper {
+    profiles: HashMap<String, Profile>,
+    active: String,
+    /// Chord buttons that stay suppressed until they are released
+    suppressed: GamepadButtons,
+    previous: GamepadButtons,
+    /// When each turbo button started being held
+    turbo_start: HashMap<GamepadButtons, Instant>,
+}
+
+impl Remapper {
+    pub const DEFAULT_PROFILE: &str = "default";
+
+    /// A remapper with `profiles`, starting out with `active`
+    pub fn new(profiles: HashMap<String, Profile>, active: &str) -> Result<Self, RemapError> {
+        ensure_profile(&profiles, active)?;
+        Ok(Self {
+            profiles,
+            active: active.to_string(),
+            suppressed: GamepadButtons::empty(),
+            previous: GamepadButtons::empty(),
+            turbo_start: HashMap::new(),
+        })
+    }
+
+    /// Load profiles from TOML. Starts with the profile named by `default`, or
+    /// an empty one named [`Self::DEFAULT_PROFILE`] if there is none
+    ///
+    /// ```toml
+    /// default = "nintendo"
+    ///
+    /// [profiles.nintendo]
+    /// buttons = { A = "B", B = "A", ZL = "L", ZR = "R" }
+    /// turbo = ["Y"]
+    /// turbo_rate = 12.0
+    /// left_stick = { invert_y = true, sensitivity = 1.2 }
+    /// chords = [{ buttons = ["HOME", "PLUS"], name = "menu" }]
+    ///
+    /// [profiles.desktop]
+    /// chords = [{ buttons = ["HOME", "MINUS"], name = "back", profile = "nintendo" }]
+    /// ```
+    ///
+    /// Buttons are named as in [`GamepadButtons`], mapping a button to `""` disables it
+    pub fn from_toml(toml: &str) -> Result<Self, RemapError> {
+        let file: ProfilesFile = toml::from_str(toml).context(ParseSnafu)?;
+        let mut profiles = file
+            .profiles
+            .into_iter()
+            .map(|(name, profile)| Ok((name, profile.try_into()?)))
+            .collect::<Result<HashMap<String, Profile>, RemapError>>()?;
+        let active = match file.default {
+            Some(name) => name,
+            None => {
+                profiles.entry(Self::DEFAULT_PROFILE.to_string()).or_default();
+                Self::DEFAULT_PROFILE.to_string()
+            }
+        };
+        for chord in profiles.values().flat_map(|profile| &profile.chords) {
+            if let Some(name) = &chord.profile {
+                ensure_profile(&profiles, name)?;
+            }
+        }
+        Self::new(profiles, &active)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RemapError> {
+        let path = path.as_ref();
+        let toml = std::fs::read_to_string(path).context(ReadSnafu { path })?;
+        Self::from_toml(&toml)
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.active
+    }
+
+    pub fn profiles(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+
+    pub fn set_profile(&mut self, name: &str) -> Result<(), RemapError> {
+        ensure_profile(&self.profiles, name)?;
+        self.active = name.to_string();
+        self.turbo_start.clear();
+        Ok(())
+    }
+
+    pub fn apply(&mut self, state: &GamepadState, time: Instant, events: &mut Vec<RemapEvent>) -> GamepadState {
+        let raw = state.buttons;
+        let pressed = raw & !self.previous;
+        self.previous = raw;
+        self.suppressed &= raw;
+
+        let profile = &self.profiles[&self.active];
+        let mut switch = None;
+        for chord in &profile.chords {
+            if raw.contains(chord.buttons) && pressed.intersects(chord.buttons) {
+                self.suppressed |= chord.buttons;
+                events.push(RemapEvent::Chord(chord.name.clone()));
+                switch = chord.profile.clone().or(switch);
+            }
+        }
+
+        let mut buttons = GamepadButtons::empty();
+        for (_, button) in (raw & !self.suppressed).iter_names() {
+            buttons |= profile
+                .buttons
+                .iter()
+                .find(|(from, _)| *from == button)
+                .map_or(button, |(_, to)| *to);
+        }
+
+        self.turbo_start.retain(|button, _| buttons.contains(*button));
+        for (_, button) in (buttons & profile.turbo).iter_names() {
+            let start = *self.turbo_start.entry(button).or_insert(time);
+            let half_periods = time.saturating_duration_since(start).as_secs_f32() * profile.turbo_rate * 2.0;
+            if half_periods as u64 % 2 == 1 {
+                buttons.remove(button);
+            }
+        }
+
+        let remapped = GamepadState {
+            buttons,
+            left_stick: profile.left_stick.apply(state.left_stick),
+            right_stick: profile.right_stick.apply(state.right_stick),
+            ..*state
+        };
+        if let Some(name) = switch {
+            if name != self.active {
+                self.active = name.clone();
+                self.turbo_start.clear();
+                events.push(RemapEvent::ProfileChanged(name));
+            }
+        }
+        remapped
+    }
+}
+
+fn ensure_profile(profiles: &HashMap<String, Profile>, name: &str) -> Result<(), RemapError> {
+    snafu::ensure!(profiles.contains_key(name), UnknownProfileSnafu { name });
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+pub enum RemapError {
+    #[snafu(display("reading profiles from {}", path.display()))]
+    Read { source: std::io::Error, path: PathBuf },
+    /// parsing profiles
+    Parse { source: toml::de::Error },
+    #[snafu(display("unknown button {name:?}"))]
+    UnknownButton { name: String },
+    #[snafu(display("unknown profile {name:?}"))]
+    UnknownProfile { name: String },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    const PROFILES: &str = r#"
+        default = "game"
+
+        [profiles.game]
+        buttons = { A = "B", B = "A", SYNC = "" }
+        turbo = ["X"]
+        turbo_rate = 10.0
+        left_stick = { invert_y = true, sensitivity = 2.0 }
+        chords = [{ buttons = ["HOME", "PLUS"], name = "desktop", profile = "desktop" }]
+
+        [profiles.desktop]
+    "#;
+
+    #[test]
+    fn profile_switching() {
+        let mut remapper = Remapper::from_toml(PROFILES).unwrap();
+        let mut events = Vec::new();
+        let time = Instant::now();
+        let mut state = GamepadState {
+            buttons: GamepadButtons::A | GamepadButtons::SYNC | GamepadButtons::X,
+            left_stick: StickPosition { x: 0.2, y: 0.3 },
+            ..Default::default()
+        };
+
+        let remapped = remapper.apply(&state, time, &mut events);
+        assert_eq!(remapped.buttons, GamepadButtons::B | GamepadButtons::X);
+        assert_eq!(remapped.left_stick, StickPosition { x: 0.4, y: -0.6 });
+        let remapped = remapper.apply(&state, time + Duration::from_millis(60), &mut events);
+        assert_eq!(remapped.buttons, GamepadButtons::B);
+
+        state.buttons = GamepadButtons::HOME | GamepadButtons::PLUS;
+        let remapped = remapper.apply(&state, time, &mut events);
+        assert!(remapped.buttons.is_empty());
+        assert_eq!(
+            events,
+            [
+                RemapEvent::Chord("desktop".into()),
+                RemapEvent::ProfileChanged("desktop".into()),
+            ]
+        );
+        state.buttons = GamepadButtons::HOME | GamepadButtons::A;
+        assert_eq!(remapper.apply(&state, time, &mut events).buttons, GamepadButtons::A);
+    }
+}
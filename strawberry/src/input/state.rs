@@ -0,0 +1,182 @@
+use crate::data::{Buttons, ExtraButtons, InputData};
+use crate::touch::{TouchCalibration, TouchPoint};
+use bitflags::bitflags;
+
+bitflags! {
+    /// Every button on the gamepad, including the stick clicks
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+    pub struct GamepadButtons: u32 {
+        const SYNC = 0x0001;
+        const HOME = 0x0002;
+        const MINUS = 0x0004;
+        const PLUS = 0x0008;
+        const R = 0x0010;
+        const L = 0x0020;
+        const ZR = 0x0040;
+        const ZL = 0x0080;
+        const DOWN = 0x0100;
+        const UP = 0x0200;
+        const RIGHT = 0x0400;
+        const LEFT = 0x0800;
+        const Y = 0x1000;
+        const X = 0x2000;
+        const B = 0x4000;
+        const A = 0x8000;
+        const TV = 0x1_0000;
+        const R3 = 0x2_0000;
+        const L3 = 0x4_0000;
+    }
+}
+
+impl GamepadButtons {
+    pub fn from_raw(buttons: Buttons, extra: ExtraButtons) -> Self {
+        let mut result = Self::from_bits_truncate(buttons.bits() as u32);
+        result.set(Self::TV, extra.contains(ExtraButtons::TV));
+        result.set(Self::R3, extra.contains(ExtraButtons::R3));
+        result.set(Self::L3, extra.contains(ExtraButtons::L3));
+        result
+    }
+}
+
+/// Raw readings of one stick axis at its limits and at rest
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AxisCalibration {
+    pub min: u16,
+    pub center: u16,
+    pub max: u16,
+}
+
+impl AxisCalibration {
+    /// Map a raw reading to -1..1, each side of the center scaled separately
+    pub fn normalize(&self, raw: u16) -> f32 {
+        let offset = raw as f32 - self.center as f32;
+        let range = if offset < 0.0 {
+            self.center.saturating_sub(self.min)
+        } else {
+            self.max.saturating_sub(self.center)
+        };
+        if range == 0 {
+            return 0.0;
+        }
+        (offset / range as f32).clamp(-1.0, 1.0)
+    }
+}
+
+impl Default for AxisCalibration {
+    fn default() -> Self {
+        Self {
+            min: 900,
+            center: 2048,
+            max: 3200,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StickConfig {
+    pub x: AxisCalibration,
+    pub y: AxisCalibration,
+    /// Deflection, as a fraction of full, below which the stick reads as centered
+    pub deadzone: f32,
+    /// Smallest deflection reported outside the deadzone, for games with their own deadzone
+    pub anti_deadzone: f32,
+}
+
+impl Default for StickConfig {
+    fn default() -> Self {
+        Self {
+            x: AxisCalibration::default(),
+            y: AxisCalibration::default(),
+            deadzone: 0.1,
+            anti_deadzone: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct InputConfig {
+    pub left_stick: StickConfig,
+    pub right_stick: StickConfig,
+    pub touch: TouchCalibration,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct StickPosition {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl StickPosition {
+    pub fn new(raw: (u16, u16), config: &StickConfig) -> Self {
+        let (x, y) = (config.x.normalize(raw.0), config.y.normalize(raw.1));
+        let magnitude = x.hypot(y);
+        if magnitude <= config.deadzone {
+            return Self::default();
+        }
+        let scaled = ((magnitude - config.deadzone) / (1.0 - config.deadzone)).min(1.0);
+        let scaled = config.anti_deadzone + scaled * (1.0 - config.anti_deadzone);
+        Self {
+            x: x / magnitude * scaled,
+            y: y / magnitude * scaled,
+        }
+    }
+
+    pub fn magnitude(&self) -> f32 {
+        self.x.hypot(self.y)
+    }
+}
+
+/// Buttons, sticks and touch decoded from an input report
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct GamepadState {
+    pub seq_id: u16,
+    pub buttons: GamepadButtons,
+    pub left_stick: StickPosition,
+    pub right_stick: StickPosition,
+    pub touch: Option<TouchPoint>,
+}
+
+impl GamepadState {
+    pub fn new(data: &InputData, config: &InputConfig) -> Self {
+        Self {
+            seq_id: data.seq_id.get(),
+            buttons: GamepadButtons::from_raw(data.buttons, data.extra_buttons),
+            left_stick: StickPosition::new(data.left_stick(), &config.left_stick),
+            right_stick: StickPosition::new(data.right_stick(), &config.right_stick),
+            touch: config.touch.decode(&data.touchscreen),
+        }
+    }
+
+    pub fn pressed(&self, buttons: GamepadButtons) -> bool {
+        self.buttons.contains(buttons)
+    }
+}
+
+impl From<&InputData> for GamepadState {
+    fn from(data: &InputData) -> Self {
+        Self::new(data, &InputConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn radial_deadzone() {
+        let config = StickConfig {
+            deadzone: 0.2,
+            anti_deadzone: 0.1,
+            ..Default::default()
+        };
+        let centered = StickPosition::new((2100, 2000), &config);
+        assert_eq!(centered, StickPosition::default());
+
+        let full = StickPosition::new((3200, 2048), &config);
+        assert_eq!(full, StickPosition { x: 1.0, y: 0.0 });
+
+        let edge = StickPosition::new((2048, 2048 - 345), &config);
+        assert!(edge.y < -0.2 && edge.y > -0.22, "{edge:?}");
+        assert_eq!(edge.x, 0.0);
+    }
+}
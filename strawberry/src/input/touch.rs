@@ -0,0 +1,79 @@
+use crate::data::Touchscreen;
+
+/// Size of the gamepad's LCD. The 864 pixel wide video stream is cropped to it
+pub const PANEL_WIDTH: u16 = 854;
+pub const PANEL_HEIGHT: u16 = 480;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TouchPoint {
+    /// Position in panel pixels
+    pub x: f32,
+    pub y: f32,
+    /// Raw 12 bit pressure reading
+    pub pressure: u16,
+}
+
+/// Maps raw touchscreen readings to panel pixels from two reference points,
+/// as stored in the pad's factory calibration
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TouchCalibration {
+    pub raw: [(u16, u16); 2],
+    pub screen: [(u16, u16); 2],
+}
+
+impl TouchCalibration {
+    pub fn map(&self, (x, y): (u16, u16)) -> (f32, f32) {
+        fn axis(value: u16, raw: (u16, u16), screen: (u16, u16), size: u16) -> f32 {
+            let span = raw.1 as f32 - raw.0 as f32;
+            if span == 0.0 {
+                return 0.0;
+            }
+            let scale = (screen.1 as f32 - screen.0 as f32) / span;
+            let position = screen.0 as f32 + (value as f32 - raw.0 as f32) * scale;
+            position.clamp(0.0, (size - 1) as f32)
+        }
+        let [(rx0, ry0), (rx1, ry1)] = self.raw;
+        let [(sx0, sy0), (sx1, sy1)] = self.screen;
+        (
+            axis(x, (rx0, rx1), (sx0, sx1), PANEL_WIDTH),
+            axis(y, (ry0, ry1), (sy0, sy1), PANEL_HEIGHT),
+        )
+    }
+
+    /// Average the samples in `touchscreen`, `None` while it isn't touched
+    pub fn decode(&self, touchscreen: &Touchscreen) -> Option<TouchPoint> {
+        let (x, y) = self.map(touchscreen.position()?);
+        Some(TouchPoint {
+            x,
+            y,
+            pressure: touchscreen.pressure(),
+        })
+    }
+}
+
+impl Default for TouchCalibration {
+    /// Stretches the full 12 bit range over the panel, until the pad's own calibration is loaded
+    fn default() -> Self {
+        Self {
+            raw: [(0, 0), (0x0fff, 0x0fff)],
+            screen: [(0, 0), (PANEL_WIDTH - 1, PANEL_HEIGHT - 1)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn two_point_mapping() {
+        let calibration = TouchCalibration {
+            raw: [(200, 3800), (3900, 300)],
+            screen: [(50, 50), (800, 430)],
+        };
+        assert_eq!(calibration.map((200, 3800)), (50.0, 50.0));
+        assert_eq!(calibration.map((3900, 300)), (800.0, 430.0));
+        let (x, y) = calibration.map((2050, 2050));
+        assert!((x - 425.0).abs() < 0.01 && (y - 240.0).abs() < 0.01);
+    }
+}
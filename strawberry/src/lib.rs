@@ -1,10 +1,12 @@
 mod input;
+mod net;
 mod video;
 pub mod cmd;
 
 use std::net::Ipv4Addr;
-pub use input::{data, InputReader};
-pub use video::{Streamer, Error as StreamerError, frame};
+pub use input::{data, dsu, event, gesture, motion, pointer, power, record, remap, state, touch, EventReceiver, InputHub, InputReader, InputStats};
+pub use net::DEFAULT_MTU;
+pub use video::{Streamer, Error as StreamerError, config, frame, mic, mixer, pcm, rumble, stats, volume};
 
 pub struct Gamepad {
     addr: Ipv4Addr,
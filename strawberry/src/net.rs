@@ -0,0 +1,35 @@
+use pnet::ipnetwork::IpNetwork;
+use std::fs;
+use std::net::Ipv4Addr;
+
+/// Address of the host on the gamepad's network
+pub(crate) const HOST_ADDR: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 10);
+/// MTU assumed when it can't be read from the interface
+pub const DEFAULT_MTU: usize = 1500;
+/// IPv4 and UDP headers in front of every datagram
+pub(crate) const IP_UDP_OVERHEAD: usize = 20 + 8;
+
+pub(crate) fn get_interface_of_ipv4(addr: Ipv4Addr) -> Option<String> {
+    let ifa = pnet::datalink::interfaces().into_iter().find(|ifa| {
+        for ip in &ifa.ips {
+            if let IpNetwork::V4(ip) = ip
+                && ip.is_supernet_of(addr.into())
+            {
+                return true;
+            }
+        }
+        false
+    });
+    ifa.map(|ifa| ifa.name)
+}
+
+pub(crate) fn interface_mtu(addr: Ipv4Addr) -> Option<usize> {
+    let iface = get_interface_of_ipv4(addr)?;
+    let mtu = fs::read_to_string(format!("/sys/class/net/{iface}/mtu")).ok()?;
+    mtu.trim().parse().ok()
+}
+
+/// MTU of the interface the gamepad is reached through
+pub(crate) fn host_mtu() -> usize {
+    interface_mtu(HOST_ADDR).unwrap_or(DEFAULT_MTU)
+}
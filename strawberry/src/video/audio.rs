@@ -0,0 +1,357 @@
+use crate::config::{AudioConfig, Overflow};
+use crate::stats::AudioStats;
+use crate::video::data::{AstrmHeader, SeqId};
+use crate::video::mixer::Mixer;
+use crate::video::rumble::RumbleState;
+use crate::video::tsf::{Timestamp, Tsf};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+pub const SAMPLE_RATE: usize = 48000;
+pub const CHANNELS: usize = 2;
+pub const SAMPLES_PER_PACKET: usize = 384;
+pub const BYTES_PER_FRAME: usize = CHANNELS * size_of::<i16>();
+const BYTES_PER_PACKET: usize = SAMPLES_PER_PACKET * BYTES_PER_FRAME;
+const PACKET_INTERVAL: Duration = Duration::from_millis(8);
+/// Timestamped audio this late is dropped instead of played
+const MAX_LATE: i32 = 20000;
+
+/// State shared between the streamer and the audio loop
+pub struct AudioState {
+    pub mixer: Mutex<Mixer>,
+    pub rumble: Mutex<RumbleState>,
+}
+
+impl AudioState {
+    pub fn new(config: AudioConfig) -> Self {
+        Self {
+            mixer: Mutex::new(Mixer::new(config)),
+            rumble: Mutex::default(),
+        }
+    }
+}
+
+/// Interleaved s16le stereo samples waiting to be sent
+pub struct AudioQueue {
+    config: AudioConfig,
+    data: VecDeque<u8>,
+    /// TSF at which the first queued sample should play, `None` plays it as soon as possible
+    due: Option<Timestamp>,
+    /// fractional sample frame position carried over between packets
+    phase: f64,
+    /// smoothed number of queued sample frames
+    fill: f64,
+    /// playback speed, input frames per output frame
+    ratio: f64,
+    playing: bool,
+    overflowing: bool,
+    stats: AudioStats,
+}
+
+impl AudioQueue {
+    /// Weight of the newest fill level in the smoothed fill level, ~10s time constant
+    const FILL_SMOOTHING: f64 = 0.0008;
+    /// Largest speed correction for clock drift
+    const MAX_DRIFT: f64 = 0.002;
+    /// Fill level error, relative to the target latency, at which drift correction saturates
+    const DRIFT_RANGE: f64 = 0.25;
+    /// Speed-up while catching up on an overflow, see [`Overflow::SpeedUp`]
+    const SPEED_UP: f64 = 0.05;
+
+    pub fn new(config: AudioConfig) -> Self {
+        let fill = Self::micros_to_bytes(config.target_latency.as_micros() as u32) as f64
+            / BYTES_PER_FRAME as f64;
+        Self {
+            config,
+            data: VecDeque::new(),
+            due: None,
+            phase: 0.0,
+            fill,
+            ratio: 1.0,
+            playing: false,
+            overflowing: false,
+            stats: AudioStats::default(),
+        }
+    }
+
+    pub fn push(&mut self, data: impl IntoIterator<Item = u8>) {
+        self.data.extend(data);
+        self.limit();
+    }
+
+    pub fn push_at(&mut self, data: impl IntoIterator<Item = u8>, due: Timestamp) {
+        if self.data.is_empty() {
+            self.due = Some(due);
+        }
+        self.push(data);
+    }
+
+    pub fn stats(&self) -> AudioStats {
+        AudioStats {
+            queued: Duration::from_micros(Self::bytes_to_micros(self.data.len()) as u64),
+            drift_ppm: (self.ratio - 1.0) * 1e6,
+            ..self.stats.clone()
+        }
+    }
+
+    fn bytes_to_micros(bytes: usize) -> u32 {
+        (bytes / BYTES_PER_FRAME * 1000000 / SAMPLE_RATE) as u32
+    }
+
+    fn micros_to_bytes(micros: u32) -> usize {
+        micros as usize * SAMPLE_RATE / 1000000 * BYTES_PER_FRAME
+    }
+
+    fn latency_bytes(latency: Duration) -> usize {
+        Self::micros_to_bytes(latency.as_micros() as u32)
+    }
+
+    fn drop_front(&mut self, bytes: usize) {
+        let bytes = usize::min(bytes, self.data.len()) / BYTES_PER_FRAME * BYTES_PER_FRAME;
+        self.data.drain(..bytes);
+        self.due = self
+            .due
+            .map(|due| due.add_micros(Self::bytes_to_micros(bytes)));
+        self.stats.dropped_frames += (bytes / BYTES_PER_FRAME) as u64;
+    }
+
+    /// Keep the queue bounded after a push
+    fn limit(&mut self) {
+        let max = Self::latency_bytes(self.config.max_latency);
+        let overflowing = self.data.len() > max;
+        if overflowing && !self.overflowing {
+            self.stats.overruns += 1;
+        }
+        self.overflowing = overflowing;
+        // speeding up only gets to play faster up to twice the maximum latency
+        let limit = match self.config.overflow {
+            Overflow::Drop => max,
+            Overflow::SpeedUp => max * 2,
+        };
+        if self.data.len() > limit {
+            let target = Self::latency_bytes(self.config.target_latency);
+            self.drop_front(self.data.len() - target);
+            self.overflowing = false;
+        }
+    }
+
+    /// Adjust the playback speed towards the target latency
+    fn update_ratio(&mut self) {
+        let queued = (self.data.len() / BYTES_PER_FRAME) as f64;
+        self.fill += (queued - self.fill) * Self::FILL_SMOOTHING;
+        let target = (Self::latency_bytes(self.config.target_latency) / BYTES_PER_FRAME) as f64;
+        let drift = ((self.fill - target) / target / Self::DRIFT_RANGE * Self::MAX_DRIFT)
+            .clamp(-Self::MAX_DRIFT, Self::MAX_DRIFT);
+        let speeding_up = self.config.overflow == Overflow::SpeedUp
+            && self.data.len() > Self::latency_bytes(self.config.target_latency)
+            && (self.overflowing || self.ratio > 1.0 + Self::MAX_DRIFT);
+        self.ratio = if speeding_up {
+            1.0 + Self::SPEED_UP
+        } else {
+            1.0 + drift
+        };
+    }
+
+    fn frame(&self, index: usize) -> [f32; CHANNELS] {
+        let offset = index * BYTES_PER_FRAME;
+        std::array::from_fn(|channel| {
+            let offset = offset + channel * size_of::<i16>();
+            i16::from_le_bytes([self.data[offset], self.data[offset + 1]]) as f32
+        })
+    }
+
+    /// Fill `frames` with the samples to play at `now`, returning the timestamp of the first one
+    pub fn fill(&mut self, now: Timestamp, frames: &mut [[f32; CHANNELS]]) -> Timestamp {
+        if let Some(due) = self.due {
+            let late = now.since(due);
+            if late < -(PACKET_INTERVAL.as_micros() as i32) {
+                frames.fill([0.0; CHANNELS]);
+                return now;
+            }
+            if late > MAX_LATE {
+                self.drop_front(Self::micros_to_bytes(late as u32));
+            }
+        }
+        // timestamped audio follows the TSF, only free-running audio needs drift correction
+        match self.due {
+            Some(_) => self.ratio = 1.0,
+            None => self.update_ratio(),
+        }
+
+        let available = self.data.len() / BYTES_PER_FRAME;
+        let mut position = self.phase;
+        let mut written = 0;
+        for out in frames.iter_mut() {
+            let index = position as usize;
+            let fraction = position - index as f64;
+            *out = if fraction == 0.0 && index < available {
+                self.frame(index)
+            } else if index + 1 < available {
+                let (a, b) = (self.frame(index), self.frame(index + 1));
+                std::array::from_fn(|channel| a[channel] + (b[channel] - a[channel]) * fraction as f32)
+            } else {
+                break;
+            };
+            position += self.ratio;
+            written += 1;
+        }
+        frames[written..].fill([0.0; CHANNELS]);
+
+        let full = written == frames.len();
+        if self.playing && !full {
+            self.stats.underruns += 1;
+        }
+        self.playing = full;
+
+        let consumed = usize::min(position as usize, available);
+        self.phase = if full { position - consumed as f64 } else { 0.0 };
+        let timestamp = self.due.unwrap_or(now);
+        self.data.drain(..consumed * BYTES_PER_FRAME);
+        self.due = if self.data.is_empty() {
+            None
+        } else {
+            self.due
+                .map(|due| due.add_micros(Self::bytes_to_micros(consumed * BYTES_PER_FRAME)))
+        };
+        timestamp
+    }
+}
+
+pub async fn audio_loop(connection: Arc<UdpSocket>, audio: Arc<AudioState>) {
+    let mut next_time = tokio::time::Instant::now();
+    let mut tsf = Tsf::new();
+    let mut packet = vec![0u8; AstrmHeader::SIZE + BYTES_PER_PACKET];
+    let mut seq_id = SeqId::default();
+    loop {
+        let timestamp = audio
+            .mixer
+            .lock()
+            .unwrap()
+            .fill_packet(tsf.wire_timestamp(), &mut packet[AstrmHeader::SIZE..]);
+        let header = AstrmHeader {
+            format: AstrmHeader::FORMAT_PCM,
+            vibrate: audio.rumble.lock().unwrap().is_on(),
+            seq_id,
+            payload_size: BYTES_PER_PACKET as u16,
+            timestamp: timestamp.0,
+            ..Default::default()
+        };
+        packet[..AstrmHeader::SIZE].copy_from_slice(&header.into_bytes());
+        seq_id = seq_id.next();
+        connection.send(&packet).await.expect("uh oh");
+
+        next_time += PACKET_INTERVAL;
+        if !next_time.elapsed().is_zero() {
+            eprintln!("audio behind deadline");
+        }
+        tokio::time::sleep_until(next_time).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn samples(frames: usize) -> impl Iterator<Item = u8> {
+        (0..frames as i16).flat_map(|sample| [sample, sample]).flat_map(i16::to_le_bytes)
+    }
+
+    #[test]
+    fn timestamped_audio_alignment() {
+        let mut queue = AudioQueue::new(AudioConfig::default());
+        let mut frames = [[0.0; CHANNELS]; SAMPLES_PER_PACKET];
+        // the same TSF a frame pushed at this media time is presented at
+        let due = Timestamp(u32::MAX - 4000);
+        queue.push_at(samples(SAMPLES_PER_PACKET * 8), due);
+
+        // too early, silence stamped with the current time
+        let now = Timestamp(due.0 - 20000);
+        assert_eq!(queue.fill(now, &mut frames), now);
+        assert_eq!(frames[0], [0.0; 2]);
+
+        // packets are stamped with the due time of their first sample, across the wrap
+        assert_eq!(queue.fill(due, &mut frames), due);
+        assert_eq!(frames[1], [1.0; 2]);
+        let next = due.add_micros(8000);
+        assert_eq!(queue.fill(next, &mut frames), next);
+        assert_eq!(frames[0], [384.0; 2]);
+
+        // far behind, the late samples are dropped so the rest stays in sync
+        let late = next.add_micros(8000 + 24000);
+        assert_eq!(queue.fill(late, &mut frames), late);
+        assert_eq!(frames[0], [(2 * 384 + 1152) as f32; 2]);
+    }
+
+    fn millis(ms: usize) -> usize {
+        SAMPLE_RATE * ms / 1000
+    }
+
+    #[test]
+    fn drop_bounds_latency() {
+        let mut queue = AudioQueue::new(AudioConfig::default());
+        queue.push(samples(millis(150)));
+        assert_eq!(queue.stats().overruns, 0);
+        queue.push(samples(millis(100)));
+        let stats = queue.stats();
+        assert_eq!(stats.overruns, 1);
+        assert_eq!(stats.queued, Duration::from_millis(40));
+        assert_eq!(stats.dropped_frames, millis(210) as u64);
+        queue.push(samples(millis(200)));
+        assert_eq!(queue.stats().overruns, 2);
+    }
+
+    #[test]
+    fn speed_up_plays_faster() {
+        let mut queue = AudioQueue::new(AudioConfig {
+            overflow: Overflow::SpeedUp,
+            ..Default::default()
+        });
+        let mut frames = [[0.0; CHANNELS]; SAMPLES_PER_PACKET];
+        queue.push(samples(millis(300)));
+        let stats = queue.stats();
+        assert_eq!((stats.overruns, stats.dropped_frames), (1, 0));
+        queue.fill(Timestamp(0), &mut frames);
+        assert_eq!(queue.stats().drift_ppm.round(), 50000.0);
+        assert_eq!(queue.data.len() / BYTES_PER_FRAME, millis(300) - 403);
+
+        // speeding up has its limits too
+        queue.push(samples(millis(200)));
+        assert_eq!(queue.stats().queued, Duration::from_millis(40));
+    }
+
+    #[test]
+    fn underruns() {
+        let mut queue = AudioQueue::new(AudioConfig::default());
+        let mut frames = [[0.0; CHANNELS]; SAMPLES_PER_PACKET];
+        queue.push(samples(SAMPLES_PER_PACKET * 3 / 2));
+        queue.fill(Timestamp(0), &mut frames);
+        assert_eq!(queue.stats().underruns, 0);
+        queue.fill(Timestamp(0), &mut frames);
+        assert_eq!(frames[SAMPLES_PER_PACKET / 2], [0.0; 2]);
+        queue.fill(Timestamp(0), &mut frames);
+        assert_eq!(queue.stats().underruns, 1);
+    }
+
+    #[test]
+    fn drift_correction() {
+        let mut frames = [[0.0; CHANNELS]; SAMPLES_PER_PACKET];
+        let mut run = |initial| {
+            let mut queue = AudioQueue::new(AudioConfig::default());
+            queue.push(samples(millis(initial)));
+            for _ in 0..3000 {
+                queue.push(samples(SAMPLES_PER_PACKET));
+                queue.fill(Timestamp(0), &mut frames);
+            }
+            queue.stats()
+        };
+        // pushes keep pace with playback, speed correction moves the queue towards the 40ms target
+        let ahead = run(100);
+        assert_eq!(ahead.drift_ppm.round(), 2000.0);
+        assert!(ahead.queued < Duration::from_millis(80));
+        let behind = run(20);
+        assert!(behind.queued > Duration::from_millis(30));
+        assert_eq!(behind.underruns + ahead.underruns, 0);
+    }
+}
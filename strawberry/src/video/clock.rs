@@ -0,0 +1,80 @@
+use crate::video::tsf::{Timestamp, Tsf};
+use std::time::Duration;
+
+/// Maps presentation timestamps of pushed media onto the TSF, shared by audio and video
+pub struct MediaClock {
+    tsf: Tsf,
+    timeline: Timeline,
+}
+
+impl MediaClock {
+    /// How long before its presentation a frame is handed to the video runner
+    pub const FRAME_LEAD: i32 = 40000;
+
+    pub fn new() -> Self {
+        Self {
+            tsf: Tsf::new(),
+            timeline: Timeline::default(),
+        }
+    }
+
+    pub fn now(&mut self) -> Timestamp {
+        self.tsf.wire_timestamp()
+    }
+
+    /// TSF at which media time `pts` is presented. The first call anchors the timeline
+    /// on its `pts`, so streams don't have to start at media time 0
+    pub fn due(&mut self, pts: Duration) -> Timestamp {
+        let now = self.tsf.wire_timestamp();
+        self.timeline.due(pts, now)
+    }
+
+    /// Forget the anchor, the next timestamped push starts a new timeline
+    pub fn reset(&mut self) {
+        self.timeline = Timeline::default();
+    }
+}
+
+/// Media time to TSF mapping of a [`MediaClock`]
+#[derive(Debug, Default)]
+struct Timeline {
+    /// TSF of media time 0
+    anchor: Option<Timestamp>,
+}
+
+impl Timeline {
+    /// Time between the first timestamped push and its presentation, to absorb encoding and queueing
+    const PRESENTATION_DELAY: u32 = 100000;
+
+    fn due(&mut self, pts: Duration, now: Timestamp) -> Timestamp {
+        let pts = pts.as_micros() as u32;
+        let anchor = *self
+            .anchor
+            .get_or_insert(now.add_micros(Self::PRESENTATION_DELAY.wrapping_sub(pts)));
+        anchor.add_micros(pts)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pts_schedule() {
+        let mut timeline = Timeline::default();
+        let start = Timestamp(u32::MAX - 150000);
+        // the first push is presented after the delay, whatever its pts
+        let first = timeline.due(Duration::from_secs(20), start);
+        assert_eq!(first.since(start), 100000);
+
+        // later pushes follow media time, however late they are made, across the wrap
+        let now = start.add_micros(500000);
+        for frame in 2..10 {
+            let due = timeline.due(Duration::from_secs(20) + Duration::from_millis(20 * (frame - 1)), now);
+            assert_eq!(due.since(first), 20000 * (frame as i32 - 1));
+        }
+
+        timeline = Timeline::default();
+        assert_eq!(timeline.due(Duration::ZERO, now).since(now), 100000);
+    }
+}
@@ -0,0 +1,186 @@
+use crate::net;
+use crate::video::data::VstrmHeader;
+use snafu::{ensure, Snafu};
+use std::time::Duration;
+
+/// Frame width sent to the gamepad
+pub const WIDTH: i32 = 864;
+/// Frame height sent to the gamepad
+pub const HEIGHT: i32 = 480;
+/// Upper bound on the payload of a video packet, regardless of the MTU
+pub const MAX_PAYLOAD_SIZE: usize = 1400;
+/// Smallest MTU every IPv4 link has to support
+pub const MIN_MTU: usize = 576;
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// MTU of the link to the gamepad, read from the network interface when `None`
+    pub mtu: Option<usize>,
+    pub audio: AudioConfig,
+}
+
+impl Config {
+    /// The configured MTU, or the interface's
+    pub fn mtu(&self) -> usize {
+        self.mtu.unwrap_or_else(net::host_mtu)
+    }
+
+    /// Largest vstrm payload that fits in a single unfragmented datagram
+    pub fn max_payload_size(&self) -> Result<usize, Error> {
+        let mtu = self.mtu();
+        ensure!(mtu >= MIN_MTU, MtuSnafu { mtu });
+        let payload_size = mtu - net::IP_UDP_OVERHEAD - VstrmHeader::SIZE;
+        Ok(payload_size.min(MAX_PAYLOAD_SIZE))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AudioConfig {
+    /// Amount of queued audio that drift correction steers towards
+    pub target_latency: Duration,
+    /// Amount of queued audio beyond which the queue overflows
+    pub max_latency: Duration,
+    pub overflow: Overflow,
+    /// Follow the gamepad's volume slider with this curve, see [`crate::volume::VolumeControl`]
+    pub volume: Option<VolumeCurve>,
+}
+
+impl AudioConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        ensure!(
+            !self.target_latency.is_zero() && self.target_latency < self.max_latency,
+            AudioLatencySnafu {
+                target: self.target_latency,
+                max: self.max_latency,
+            }
+        );
+        if let Some(volume) = &self.volume {
+            volume.validate()?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            target_latency: Duration::from_millis(40),
+            max_latency: Duration::from_millis(200),
+            overflow: Overflow::Drop,
+            volume: None,
+        }
+    }
+}
+
+/// Maps the raw volume slider position to a gain on outgoing audio
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VolumeCurve {
+    /// Slider positions at or below this are silent
+    pub mute_below: u8,
+    /// Slider positions at or above this play at full volume
+    pub full_above: u8,
+    /// Gain follows the slider travel between the thresholds raised to this power
+    pub exponent: f32,
+    /// Extra gain while headphones are plugged in
+    pub headphone_gain: f32,
+}
+
+impl VolumeCurve {
+    pub fn validate(&self) -> Result<(), Error> {
+        ensure!(
+            self.mute_below < self.full_above,
+            VolumeRangeSnafu {
+                mute_below: self.mute_below,
+                full_above: self.full_above,
+            }
+        );
+        Ok(())
+    }
+
+    pub fn gain(&self, slider: u8, headphones: bool) -> f32 {
+        if slider <= self.mute_below {
+            return 0.0;
+        }
+        let mute_below = self.mute_below as f32;
+        let travel = (slider as f32 - mute_below) / (self.full_above as f32 - mute_below);
+        let gain = travel.clamp(0.0, 1.0).powf(self.exponent);
+        if headphones {
+            gain * self.headphone_gain
+        } else {
+            gain
+        }
+    }
+}
+
+impl Default for VolumeCurve {
+    fn default() -> Self {
+        Self {
+            mute_below: 4,
+            full_above: u8::MAX,
+            exponent: 2.0,
+            headphone_gain: 1.0,
+        }
+    }
+}
+
+/// What to do with audio pushed beyond [`AudioConfig::max_latency`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Overflow {
+    /// drop the oldest audio, down to the target latency
+    Drop,
+    /// play about 5% faster until back at the target latency. This resamples rather than
+    /// time-stretches, so the pitch rises by the same amount while catching up
+    SpeedUp,
+}
+
+/// How a frame is split into chunks of whole macroblock rows. The x264 fork's drh
+/// mode decides the chunk size, so the layout is fixed rather than a setting
+pub struct ChunkLayout;
+
+impl ChunkLayout {
+    pub const MB_COLUMNS: u8 = ((WIDTH + 15) / 16) as u8;
+    pub const MB_ROWS: u8 = ((HEIGHT + 15) / 16) as u8;
+    pub const ROWS_PER_CHUNK: u8 = 6;
+    pub const CHUNKS_PER_FRAME: usize = (Self::MB_ROWS / Self::ROWS_PER_CHUNK) as usize;
+    pub const MBS_PER_CHUNK: i32 = Self::ROWS_PER_CHUNK as i32 * Self::MB_COLUMNS as i32;
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("MTU {mtu} is below the minimum of {MIN_MTU}"))]
+    Mtu { mtu: usize },
+    #[snafu(display("audio target latency {target:?} must be non-zero and below the maximum {max:?}"))]
+    AudioLatency { target: Duration, max: Duration },
+    #[snafu(display("volume mute threshold {mute_below} must be below the full volume threshold {full_above}"))]
+    VolumeRange { mute_below: u8, full_above: u8 },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn volume_gain() {
+        let curve = VolumeCurve {
+            mute_below: 10,
+            full_above: 110,
+            exponent: 2.0,
+            headphone_gain: 0.5,
+        };
+        assert_eq!(curve.gain(0, false), 0.0);
+        assert_eq!(curve.gain(10, false), 0.0);
+        assert_eq!(curve.gain(60, false), 0.25);
+        assert_eq!(curve.gain(110, false), 1.0);
+        assert_eq!(curve.gain(u8::MAX, false), 1.0);
+        assert_eq!(curve.gain(60, true), 0.125);
+
+        // an unvalidated curve must not panic
+        let inverted = VolumeCurve {
+            mute_below: 200,
+            full_above: 100,
+            ..curve
+        };
+        assert!(inverted.validate().is_err());
+        assert_eq!(inverted.gain(250, false), 0.0);
+    }
+}
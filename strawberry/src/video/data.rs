@@ -1,3 +1,4 @@
+use crate::video::config::ChunkLayout;
 use bitfld::layout;
 use snafu::{ensure, OptionExt, Snafu};
 use std::io::Write;
@@ -20,6 +21,31 @@ layout!({
     }
 });
 
+/// 10 bit sequence number of vstrm and astrm packets
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct SeqId(u16);
+
+impl SeqId {
+    pub const MODULUS: u16 = 1 << 10;
+
+    pub const fn new(value: u16) -> Self {
+        Self(value % Self::MODULUS)
+    }
+
+    pub const fn get(self) -> u16 {
+        self.0
+    }
+
+    pub const fn next(self) -> Self {
+        Self::new(self.0 + 1)
+    }
+
+    /// Number of steps from `earlier` to `self`, modulo the sequence space
+    pub const fn since(self, earlier: Self) -> u16 {
+        self.0.wrapping_sub(earlier.0) % Self::MODULUS
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct VstrmHeader {
     pub magic: u8,
@@ -71,11 +97,61 @@ impl Default for VstrmHeader {
             has_timestamp: true,
             payload_size: 0,
             timestamp: 0,
-            ext_headers: vec![ExtOption::ForceDecoding, ExtOption::NumMbRowsInChunk(6)],
+            ext_headers: vec![
+                ExtOption::ForceDecoding,
+                ExtOption::NumMbRowsInChunk(ChunkLayout::ROWS_PER_CHUNK),
+            ],
         }
     }
 }
 
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AstrmHeader {
+    pub format: u8,
+    pub channel: bool,
+    pub vibrate: bool,
+    pub video_format: bool,
+    pub seq_id: SeqId,
+    pub payload_size: u16,
+    pub timestamp: u32,
+}
+
+impl AstrmHeader {
+    pub const SIZE: usize = 8;
+    pub const FORMAT_PCM: u8 = 1;
+
+    pub fn into_bytes(self) -> [u8; Self::SIZE] {
+        let mut buffer = [0u8; Self::SIZE];
+        buffer[0] = self.format << 5;
+        buffer[0] |= (self.channel as u8) << 4;
+        buffer[0] |= (self.vibrate as u8) << 3;
+        buffer[0] |= (self.video_format as u8) << 2;
+        buffer[0] |= (self.seq_id.get() >> 8) as u8 & 0b11;
+        buffer[1] = self.seq_id.get() as u8;
+        buffer[2..4].copy_from_slice(&self.payload_size.to_be_bytes());
+        buffer[4..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        buffer
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        ensure!(
+            bytes.len() >= Self::SIZE,
+            AstrmLengthSnafu {
+                length: bytes.len()
+            }
+        );
+        Ok(Self {
+            format: bytes[0] >> 5,
+            channel: bytes[0] & (1 << 4) != 0,
+            vibrate: bytes[0] & (1 << 3) != 0,
+            video_format: bytes[0] & (1 << 2) != 0,
+            seq_id: SeqId::new(u16::from_be_bytes([bytes[0] & 0b11, bytes[1]])),
+            payload_size: u16::from_be_bytes([bytes[2], bytes[3]]),
+            timestamp: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+        })
+    }
+}
+
 #[repr(u8)]
 #[derive(Debug, Copy, Clone)]
 pub enum ExtOption {
@@ -207,4 +283,8 @@ pub enum Error {
     ExtHeaderParam { instr: &'static str },
     #[snafu(display("Invalid framerate value {value}"))]
     InvalidFramerate { value: u8 },
+    #[snafu(display("astrm packet is too short ({length} bytes)"))]
+    AstrmLength { length: usize },
+    #[snafu(display("astrm payload size {expected} doesn't match packet ({length} bytes)"))]
+    AstrmPayload { expected: u16, length: usize },
 }
@@ -1,3 +1,4 @@
+use crate::video::config::{ChunkLayout, HEIGHT, WIDTH};
 use snafu::{ResultExt, Snafu, ensure};
 use std::ffi::c_void;
 use strawberry_x264::{Colorspace, Encoding, Image, Preset, Tune};
@@ -7,10 +8,6 @@ pub struct Encoder {
     encoder: strawberry_x264::Encoder,
 }
 
-const WIDTH: i32 = 864;
-const HEIGHT: i32 = 480;
-const CHUNKS_PER_FRAME: i32 = 5;
-
 impl Encoder {
     pub fn new() -> Result<Self, Error> {
         let mut builder = strawberry_x264::Setup::preset(Preset::Medium, Tune::None, false, true);
@@ -97,19 +94,32 @@ impl Encoder {
         Ok(Self { encoder })
     }
 
-    pub fn encode(&mut self, image: Image, resync: bool) -> Result<([&[u8]; 5], bool), Error> {
-        let mut context: Context = Context::default();
+    pub fn encode(&mut self, image: Image, resync: bool) -> Result<(Vec<&[u8]>, bool), Error> {
+        let mut context = Context::new();
         unsafe {
             self.encoder
                 .encode_drh(image, resync, (&raw mut context).cast())
                 .map_err(|_| Error::Encoder)?;
         }
-        let chunks: [(*const u8, usize); 5] = context.chunk_array.try_into().map_err(|v: ChunkArray| Error::ChunkCount {length: v.len()})?;
-        let chunks = chunks.map(|(ptr, size)| {
-            unsafe {
-                std::slice::from_raw_parts(ptr, size)
+        if let Some(first_mb) = context.misaligned {
+            return ChunkAlignmentSnafu {
+                first_mb,
+                mbs_per_chunk: ChunkLayout::MBS_PER_CHUNK,
+            }
+            .fail();
+        }
+        ensure!(
+            context.chunk_array.len() == ChunkLayout::CHUNKS_PER_FRAME,
+            ChunkCountSnafu {
+                length: context.chunk_array.len(),
+                expected: ChunkLayout::CHUNKS_PER_FRAME,
             }
-        });
+        );
+        let chunks = context
+            .chunk_array
+            .into_iter()
+            .map(|(ptr, size)| unsafe { std::slice::from_raw_parts(ptr, size) })
+            .collect();
         Ok((chunks, context.is_idr))
     }
 }
@@ -119,13 +129,16 @@ type ChunkArray = Vec<(*const u8, usize)>;
 struct Context {
     chunk_array: ChunkArray,
     is_idr: bool,
+    /// first macroblock of a NAL unit that didn't start the next expected chunk
+    misaligned: Option<i32>,
 }
 
-impl Default for Context {
-    fn default() -> Self {
+impl Context {
+    fn new() -> Self {
         Self {
-            chunk_array: Vec::with_capacity(5),
+            chunk_array: Vec::with_capacity(ChunkLayout::CHUNKS_PER_FRAME),
             is_idr: false,
+            misaligned: None,
         }
     }
 }
@@ -138,17 +151,17 @@ fn process_nal_unit(nal: &x264_nal_t, ctx: &mut Context) {
     if nal.i_type == NAL_SEI {
         return;
     }
-    let mb_per_frame = ((WIDTH + 15) / 16) * ((HEIGHT + 15) / 16);
-    let mb_per_chunk = mb_per_frame / CHUNKS_PER_FRAME;
-    let chunk_idx = nal.i_first_mb / mb_per_chunk;
-
-    assert_eq!(chunk_idx, ctx.chunk_array.len() as i32);
+    // unwinding out of the x264 callback isn't possible, so report misalignment from `encode`
+    let chunks = ChunkLayout::CHUNKS_PER_FRAME;
+    let chunk_idx = nal.i_first_mb / ChunkLayout::MBS_PER_CHUNK;
+    if ctx.misaligned.is_some() || chunk_idx != ctx.chunk_array.len() as i32 || chunk_idx >= chunks as i32 {
+        ctx.misaligned.get_or_insert(nal.i_first_mb);
+        return;
+    }
 
     ctx.chunk_array.push((nal.p_payload, nal.i_payload as usize));
 
-    assert!(ctx.chunk_array.len() <= 5);
-
-    if ctx.chunk_array.len() == 5 {
+    if ctx.chunk_array.len() == chunks {
         ctx.is_idr = nal.i_ref_idc != NAL_PRIORITY_DISPOSABLE && nal.i_type == NAL_SLICE_IDR;
     }
 }
@@ -159,6 +172,8 @@ pub enum Error {
     EncoderBuild,
     /// encoding error
     Encoder,
-    #[snafu(display("Unexpected number of chunks {length} != 5"))]
-    ChunkCount { length: usize },
+    #[snafu(display("Unexpected number of chunks {length} != {expected}"))]
+    ChunkCount { length: usize, expected: usize },
+    #[snafu(display("NAL unit starting at macroblock {first_mb} doesn't line up with chunks of {mbs_per_chunk} macroblocks"))]
+    ChunkAlignment { first_mb: i32, mbs_per_chunk: i32 },
 }
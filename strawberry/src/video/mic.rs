@@ -0,0 +1,141 @@
+use crate::cmd::data::{UvcUacPayload, UvcUacResponse};
+use crate::cmd::{CommandHandler, Error as CommandError};
+use crate::video::data::{AstrmHeader, Error, SeqId};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// The pad sends mono s16le samples at the rate requested with `mic_freq`
+pub const SAMPLE_RATE: u16 = 16000;
+/// Pause after a failed receive, so an error that keeps repeating doesn't spin the loop
+const RECV_RETRY: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct MicPacket {
+    /// TSF timestamp (µs) of the first sample
+    pub timestamp: u32,
+    pub seq_id: u16,
+    /// Packets missing between the previous packet and this one, either lost on
+    /// the network or skipped because the receiver fell behind
+    pub lost: u64,
+    pub samples: Arc<[i16]>,
+}
+
+/// Stream of microphone packets, see [`crate::Streamer::microphone`]
+pub struct Microphone {
+    recv: broadcast::Receiver<MicPacket>,
+    lagged: u64,
+}
+
+impl Microphone {
+    pub(crate) fn new(recv: broadcast::Receiver<MicPacket>) -> Self {
+        Self { recv, lagged: 0 }
+    }
+
+    /// Wait for the next packet. Returns `None` once the streamer has stopped
+    pub async fn next(&mut self) -> Option<MicPacket> {
+        loop {
+            match self.recv.recv().await {
+                Ok(mut packet) => {
+                    packet.lost += std::mem::take(&mut self.lagged);
+                    return Some(packet);
+                }
+                Err(RecvError::Lagged(skipped)) => self.lagged += skipped,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// Turn the microphone on or off. Packets only arrive while it's enabled.
+/// `state` is the last payload sent, only its microphone fields are changed and
+/// it's updated from the response
+pub async fn set_enabled(
+    cmd: &CommandHandler,
+    state: &mut UvcUacPayload,
+    enabled: bool,
+) -> Result<UvcUacResponse, CommandError> {
+    state.mic_enable = enabled as u8;
+    state.mic_freq = SAMPLE_RATE.into();
+    let response = cmd.command(state).await?;
+    state.update(&response);
+    Ok(response)
+}
+
+/// Parse an incoming astrm packet, skipping anything that isn't PCM audio
+fn parse_packet(packet: &[u8]) -> Result<Option<(AstrmHeader, Vec<i16>)>, Error> {
+    let header = AstrmHeader::from_bytes(packet)?;
+    if header.video_format || header.format != AstrmHeader::FORMAT_PCM {
+        return Ok(None);
+    }
+    let payload = &packet[AstrmHeader::SIZE..];
+    if header.payload_size as usize != payload.len() || payload.len() % 2 != 0 {
+        return Err(Error::AstrmPayload {
+            expected: header.payload_size,
+            length: packet.len(),
+        });
+    }
+    let samples = payload
+        .chunks_exact(size_of::<i16>())
+        .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+        .collect();
+    Ok(Some((header, samples)))
+}
+
+pub async fn mic_loop(connection: Arc<UdpSocket>, send: broadcast::Sender<MicPacket>, mtu: usize) {
+    let mut buffer = vec![0u8; mtu];
+    let mut last_seq_id: Option<SeqId> = None;
+    loop {
+        let length = match connection.recv(&mut buffer).await {
+            Ok(length) => length,
+            Err(e) => {
+                eprintln!("microphone receive error: {e}");
+                tokio::time::sleep(RECV_RETRY).await;
+                continue;
+            }
+        };
+        let (header, samples) = match parse_packet(&buffer[..length]) {
+            Ok(Some(packet)) => packet,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("invalid microphone packet: {e}");
+                continue;
+            }
+        };
+        let lost = last_seq_id.map_or(0, |last| header.seq_id.since(last).saturating_sub(1));
+        last_seq_id = Some(header.seq_id);
+        let _ = send.send(MicPacket {
+            timestamp: header.timestamp,
+            seq_id: header.seq_id.get(),
+            lost: lost.into(),
+            samples: samples.into(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_pcm_packet() {
+        let header = AstrmHeader {
+            format: AstrmHeader::FORMAT_PCM,
+            seq_id: SeqId::new(1023),
+            payload_size: 4,
+            timestamp: 0xdeadbeef,
+            ..Default::default()
+        };
+        let mut packet = header.into_bytes().to_vec();
+        packet.extend([0x01, 0x00, 0xff, 0xff]);
+        let (parsed, samples) = parse_packet(&packet).unwrap().unwrap();
+        assert_eq!(parsed.seq_id, SeqId::new(1023));
+        assert_eq!(parsed.timestamp, 0xdeadbeef);
+        assert_eq!(samples, [1, -1]);
+
+        packet.pop();
+        assert!(parse_packet(&packet).is_err());
+    }
+}
@@ -0,0 +1,328 @@
+use crate::config::AudioConfig;
+use crate::pcm::{AudioBuffer, AudioConverter, Error as PcmError, Sample};
+use crate::stats::AudioStats;
+use crate::video::audio::{AudioQueue, AudioState, BYTES_PER_FRAME, CHANNELS, SAMPLES_PER_PACKET, SAMPLE_RATE};
+use crate::video::clock::MediaClock;
+use crate::video::tsf::Timestamp;
+use crate::video::{Error, PcmSnafu};
+use snafu::ResultExt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+struct Channel {
+    queue: AudioQueue,
+    gain: f32,
+    muted: bool,
+}
+
+impl Channel {
+    fn volume(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.gain
+        }
+    }
+}
+
+struct PlayingClip {
+    channel: usize,
+    clip: Clip,
+    position: usize,
+}
+
+/// Sums the audio channels and clips into the packets sent to the gamepad
+pub struct Mixer {
+    config: AudioConfig,
+    channels: Vec<Option<Channel>>,
+    clips: Vec<PlayingClip>,
+    /// Gain applied to the whole mix, driven by the volume slider
+    master: f32,
+}
+
+impl Mixer {
+    /// Channel whose timestamps the mixed packets carry
+    pub const MAIN: usize = 0;
+
+    pub fn new(config: AudioConfig) -> Self {
+        let mut mixer = Self {
+            config,
+            channels: Vec::new(),
+            clips: Vec::new(),
+            master: 1.0,
+        };
+        mixer.add_channel();
+        mixer
+    }
+
+    fn add_channel(&mut self) -> usize {
+        let channel = Channel {
+            queue: AudioQueue::new(self.config.clone()),
+            gain: 1.0,
+            muted: false,
+        };
+        match self.channels.iter().position(Option::is_none) {
+            Some(index) => {
+                self.channels[index] = Some(channel);
+                index
+            }
+            None => {
+                self.channels.push(Some(channel));
+                self.channels.len() - 1
+            }
+        }
+    }
+
+    fn remove_channel(&mut self, index: usize) {
+        self.channels[index] = None;
+        self.clips.retain(|clip| clip.channel != index);
+    }
+
+    pub fn set_master_gain(&mut self, gain: f32) {
+        self.master = gain;
+    }
+
+    fn channel(&mut self, index: usize) -> &mut Channel {
+        self.channels[index].as_mut().expect("channel is alive while its handle is")
+    }
+
+    /// Fill `packet` with the mix to play at `now`, returning the timestamp of the first sample
+    pub fn fill_packet(&mut self, now: Timestamp, packet: &mut [u8]) -> Timestamp {
+        let mut mix = [[0.0f32; CHANNELS]; SAMPLES_PER_PACKET];
+        let mut frames = [[0.0f32; CHANNELS]; SAMPLES_PER_PACKET];
+        // the main channel comes first and decides the timestamp, the others are played alongside it
+        let mut timestamp = None;
+        for channel in self.channels.iter_mut().flatten() {
+            let ts = channel.queue.fill(timestamp.unwrap_or(now), &mut frames);
+            timestamp.get_or_insert(ts);
+            let volume = channel.volume();
+            for (out, frame) in mix.iter_mut().zip(&frames) {
+                for (out, sample) in out.iter_mut().zip(frame) {
+                    *out += sample * volume;
+                }
+            }
+        }
+
+        let channels = &self.channels;
+        self.clips.retain_mut(|playing| {
+            let volume = channels[playing.channel].as_ref().map_or(0.0, Channel::volume);
+            let remaining = &playing.clip.0[playing.position..];
+            for (out, frame) in mix.iter_mut().zip(remaining) {
+                for (out, sample) in out.iter_mut().zip(frame) {
+                    *out += sample * volume;
+                }
+            }
+            playing.position += usize::min(remaining.len(), SAMPLES_PER_PACKET);
+            playing.position < playing.clip.0.len()
+        });
+
+        for (out, frame) in packet.chunks_exact_mut(BYTES_PER_FRAME).zip(mix) {
+            for (dst, sample) in out.chunks_exact_mut(size_of::<i16>()).zip(frame) {
+                let sample = (sample * self.master).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+                dst.copy_from_slice(&sample.to_le_bytes());
+            }
+        }
+        timestamp.unwrap_or(now)
+    }
+}
+
+/// Sound converted to the gamepad's format up front, to be played any number of times
+#[derive(Clone)]
+pub struct Clip(Arc<[[f32; CHANNELS]]>);
+
+impl Clip {
+    pub fn new<S: Sample>(buffer: AudioBuffer<S>, sample_rate: u32) -> Result<Self, PcmError> {
+        let mut converter = AudioConverter::new(sample_rate, buffer.channels())?;
+        let mut samples = Vec::new();
+        converter.convert(&buffer, &mut samples)?;
+        converter.flush(&mut samples);
+        Ok(Self(
+            samples
+                .chunks_exact(CHANNELS)
+                .map(|frame| std::array::from_fn(|channel| frame[channel] as f32))
+                .collect(),
+        ))
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.0.len() as f64 / SAMPLE_RATE as f64)
+    }
+}
+
+/// Handle to one input of the mixer, with its own queue, gain and mute.
+/// The channel is removed when the handle is dropped
+pub struct AudioChannel {
+    index: usize,
+    audio: Arc<AudioState>,
+    clock: Arc<Mutex<MediaClock>>,
+    converter: Mutex<Option<AudioConverter>>,
+}
+
+impl AudioChannel {
+    pub(crate) fn main(audio: Arc<AudioState>, clock: Arc<Mutex<MediaClock>>) -> Self {
+        Self {
+            index: Mixer::MAIN,
+            audio,
+            clock,
+            converter: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn new(audio: Arc<AudioState>, clock: Arc<Mutex<MediaClock>>) -> Self {
+        let index = audio.mixer.lock().unwrap().add_channel();
+        Self {
+            index,
+            audio,
+            clock,
+            converter: Mutex::new(None),
+        }
+    }
+
+    /// Queue s16le stereo samples to play as soon as possible
+    pub fn push_audio(&self, data: impl IntoIterator<Item = u8>) {
+        let mut mixer = self.audio.mixer.lock().unwrap();
+        mixer.channel(self.index).queue.push(data);
+    }
+
+    /// Play s16le stereo samples starting at media time `pts`, on the same timeline as [`crate::Streamer::push_frame_at`]
+    pub fn push_audio_at(&self, data: impl IntoIterator<Item = u8>, pts: Duration) {
+        let due = self.clock.lock().unwrap().due(pts);
+        let mut mixer = self.audio.mixer.lock().unwrap();
+        mixer.channel(self.index).queue.push_at(data, due);
+    }
+
+    /// Downmix and resample `buffer` to the 48 kHz stereo s16le the gamepad plays, and queue it
+    pub fn push_samples<S: Sample>(&self, buffer: AudioBuffer<S>, sample_rate: u32) -> Result<(), Error> {
+        let (samples, _) = self.convert_samples(&buffer, sample_rate)?;
+        self.push_audio(samples.iter().flat_map(|sample| sample.to_le_bytes()));
+        Ok(())
+    }
+
+    /// Like [`Self::push_samples`], starting at media time `pts`
+    pub fn push_samples_at<S: Sample>(
+        &self,
+        buffer: AudioBuffer<S>,
+        sample_rate: u32,
+        pts: Duration,
+    ) -> Result<(), Error> {
+        let (samples, lag) = self.convert_samples(&buffer, sample_rate)?;
+        self.push_audio_at(
+            samples.iter().flat_map(|sample| sample.to_le_bytes()),
+            pts.saturating_sub(lag),
+        );
+        Ok(())
+    }
+
+    fn convert_samples<S: Sample>(
+        &self,
+        buffer: &AudioBuffer<S>,
+        sample_rate: u32,
+    ) -> Result<(Vec<i16>, Duration), Error> {
+        let mut converter = self.converter.lock().unwrap();
+        let converter = match &mut *converter {
+            Some(converter)
+                if converter.sample_rate() == sample_rate
+                    && converter.channels() == buffer.channels() =>
+            {
+                converter
+            }
+            converter => converter.insert(
+                AudioConverter::new(sample_rate, buffer.channels()).context(PcmSnafu)?,
+            ),
+        };
+        let mut samples = Vec::new();
+        let lag = converter.convert(buffer, &mut samples).context(PcmSnafu)?;
+        Ok((samples, lag))
+    }
+
+    /// Play `clip` once on top of the queued audio, fire and forget
+    pub fn play(&self, clip: &Clip) {
+        let mut mixer = self.audio.mixer.lock().unwrap();
+        mixer.clips.push(PlayingClip {
+            channel: self.index,
+            clip: clip.clone(),
+            position: 0,
+        });
+    }
+
+    /// Linear gain applied to everything played on this channel
+    pub fn set_gain(&self, gain: f32) {
+        self.audio.mixer.lock().unwrap().channel(self.index).gain = gain;
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.audio.mixer.lock().unwrap().channel(self.index).muted = muted;
+    }
+
+    pub fn stats(&self) -> AudioStats {
+        self.audio.mixer.lock().unwrap().channel(self.index).queue.stats()
+    }
+}
+
+impl Drop for AudioChannel {
+    fn drop(&mut self) {
+        self.audio.mixer.lock().unwrap().remove_channel(self.index);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(packet: &[u8], index: usize) -> [i16; 2] {
+        let offset = index * BYTES_PER_FRAME;
+        [
+            i16::from_le_bytes([packet[offset], packet[offset + 1]]),
+            i16::from_le_bytes([packet[offset + 2], packet[offset + 3]]),
+        ]
+    }
+
+    fn samples(value: i16) -> impl Iterator<Item = u8> {
+        std::iter::repeat_n(value.to_le_bytes(), SAMPLES_PER_PACKET * CHANNELS).flatten()
+    }
+
+    fn mix(main: i16, voice: i16, gain: f32, muted: bool) -> Mixer {
+        let mut mixer = Mixer::new(AudioConfig::default());
+        let index = mixer.add_channel();
+        mixer.channel(Mixer::MAIN).queue.push(samples(main));
+        let channel = mixer.channel(index);
+        channel.queue.push(samples(voice));
+        channel.gain = gain;
+        channel.muted = muted;
+        mixer
+    }
+
+    #[test]
+    fn clip_keeps_its_end() {
+        let input = vec![0.5f32; 22050];
+        let clip = Clip::new(AudioBuffer::Planar(&[&input]), 44100).unwrap();
+        let error = clip.duration().as_secs_f64() - 0.5;
+        assert!(error.abs() <= 1.0 / SAMPLE_RATE as f64, "{:?}", clip.duration());
+    }
+
+    #[test]
+    fn gain_and_mute() {
+        let mut packet = vec![0u8; SAMPLES_PER_PACKET * BYTES_PER_FRAME];
+        mix(1000, 1000, 0.5, false).fill_packet(Timestamp(0), &mut packet);
+        assert_eq!(frame(&packet, 0), [1500, 1500]);
+        mix(1000, 1000, 0.5, true).fill_packet(Timestamp(0), &mut packet);
+        assert_eq!(frame(&packet, 0), [1000, 1000]);
+    }
+
+    #[test]
+    fn clipping() {
+        let mut packet = vec![0u8; SAMPLES_PER_PACKET * BYTES_PER_FRAME];
+        mix(-30000, -30000, 2.0, false).fill_packet(Timestamp(0), &mut packet);
+        assert_eq!(frame(&packet, 0), [i16::MIN, i16::MIN]);
+
+        let mut mixer = mix(30000, 0, 1.0, false);
+        mixer.clips.push(PlayingClip {
+            channel: Mixer::MAIN,
+            clip: Clip(vec![[20000.0; CHANNELS]; 10].into()),
+            position: 0,
+        });
+        mixer.fill_packet(Timestamp(0), &mut packet);
+        assert_eq!(frame(&packet, 0), [i16::MAX, i16::MAX]);
+        assert!(mixer.clips.is_empty());
+    }
+}
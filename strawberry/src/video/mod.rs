@@ -1,15 +1,32 @@
+mod audio;
+mod clock;
+pub mod config;
 mod data;
 mod encoder;
 pub mod frame;
+pub mod mic;
+pub mod mixer;
+pub mod pcm;
+pub mod rumble;
+pub mod stats;
 mod tsf;
-
+pub mod volume;
+
+use crate::config::{ChunkLayout, Config, Error as ConfigError};
+use crate::mixer::AudioChannel;
+use crate::pcm::{AudioBuffer, Error as PcmError, Sample};
+use crate::rumble::Rumble;
+use crate::video::audio::{audio_loop, AudioState};
+use crate::video::clock::MediaClock;
 use crate::frame::Frame;
-use crate::video::data::{ExtOption, FrameRate, VstrmHeader};
-use crate::video::tsf::Tsf;
+use crate::mic::{mic_loop, MicPacket, Microphone};
+use crate::stats::{AudioStats, FrameStats};
+use crate::video::data::{ExtOption, FrameRate, SeqId, VstrmHeader};
+use crate::video::tsf::{Timestamp, Tsf};
+use crate::volume::VolumeControl;
 pub use data::Error as DataError;
 pub use encoder::{Encoder, Error as EncoderError};
 use snafu::{Report, ResultExt, Snafu};
-use std::collections::VecDeque;
 use std::io::Write;
 use std::process::Termination;
 use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
@@ -18,44 +35,155 @@ use std::time::{Duration, Instant};
 use tokio::io::AsyncWriteExt;
 use tokio::net::UdpSocket;
 use tokio::runtime::Handle;
-use tokio::sync::watch;
-
-const MAX_PAYLOAD_SIZE: usize = 1400;
+use tokio::sync::{broadcast, watch};
 
 pub struct Streamer<T: Frame + Send + Sync> {
-    send: watch::Sender<Option<T>>,
-    audio_queue: Arc<Mutex<VecDeque<u8>>>,
+    send: watch::Sender<Option<QueuedFrame<T>>>,
+    audio: Arc<AudioState>,
+    clock: Arc<Mutex<MediaClock>>,
+    main: AudioChannel,
+    volume: VolumeControl,
+    stats: broadcast::Sender<FrameStats>,
+    mic: broadcast::Sender<MicPacket>,
+}
+
+struct QueuedFrame<T> {
+    frame: T,
+    /// TSF to present the frame at, `None` shows it on the next tick
+    due: Option<Timestamp>,
 }
 
 impl<T: Frame + Send + Sync + 'static> Streamer<T> {
     pub async fn new() -> Result<Self, Error> {
+        Self::with_config(Config::default()).await
+    }
+
+    pub async fn with_config(config: Config) -> Result<Self, Error> {
+        config.audio.validate().context(ConfigSnafu)?;
         let (send, recv) = watch::channel(None);
-        let audio_queue = Default::default();
-        VideoRunner::spawn(recv, Arc::clone(&audio_queue));
-        Ok(Self { send, audio_queue })
+        let audio = Arc::new(AudioState::new(config.audio.clone()));
+        let (stats, _) = broadcast::channel(16);
+        let (mic, _) = broadcast::channel(64);
+        let volume = VolumeControl::new(config.audio.volume, Arc::clone(&audio));
+        VideoRunner::spawn(config, recv, Arc::clone(&audio), stats.clone(), mic.clone());
+        let clock = Arc::new(Mutex::new(MediaClock::new()));
+        Ok(Self {
+            send,
+            main: AudioChannel::main(Arc::clone(&audio), Arc::clone(&clock)),
+            volume,
+            audio,
+            clock,
+            stats,
+            mic,
+        })
     }
 
     pub fn push_frame(&self, frame: T) -> Result<(), Error> {
-        self.send.send(Some(frame)).map_err(|_| Error::Queue)?;
+        self.send
+            .send(Some(QueuedFrame { frame, due: None }))
+            .map_err(|_| Error::Queue)?;
+        Ok(())
+    }
+
+    /// Present `frame` at media time `pts`, on the same timeline as [`Self::push_audio_at`].
+    /// Waits until the frame is almost due, so it can be called with frames decoded ahead of time
+    pub async fn push_frame_at(&self, frame: T, pts: Duration) -> Result<(), Error> {
+        let (due, now) = {
+            let mut clock = self.clock.lock().unwrap();
+            (clock.due(pts), clock.now())
+        };
+        let wait = due.since(now) - MediaClock::FRAME_LEAD;
+        if wait > 0 {
+            tokio::time::sleep(Duration::from_micros(wait as u64)).await;
+        }
+        self.send
+            .send(Some(QueuedFrame {
+                frame,
+                due: Some(due),
+            }))
+            .map_err(|_| Error::Queue)?;
         Ok(())
     }
 
     pub fn push_audio(&self, data: impl IntoIterator<Item = u8>) {
-        let mut guard = self.audio_queue.lock().unwrap();
-        guard.extend(data);
+        self.main.push_audio(data);
+    }
+
+    /// Play s16le stereo samples starting at media time `pts`, on the same timeline as [`Self::push_frame_at`]
+    pub fn push_audio_at(&self, data: impl IntoIterator<Item = u8>, pts: Duration) {
+        self.main.push_audio_at(data, pts);
+    }
+
+    /// Downmix and resample `buffer` to the 48 kHz stereo s16le the gamepad plays, and queue it
+    pub fn push_samples<S: Sample>(&self, buffer: AudioBuffer<S>, sample_rate: u32) -> Result<(), Error> {
+        self.main.push_samples(buffer, sample_rate)
+    }
+
+    /// Like [`Self::push_samples`], starting at media time `pts`
+    pub fn push_samples_at<S: Sample>(
+        &self,
+        buffer: AudioBuffer<S>,
+        sample_rate: u32,
+        pts: Duration,
+    ) -> Result<(), Error> {
+        self.main.push_samples_at(buffer, sample_rate, pts)
+    }
+
+    /// The channel the `push_audio` and `push_samples` methods feed, whose timestamps
+    /// the gamepad follows
+    pub fn main_channel(&self) -> &AudioChannel {
+        &self.main
+    }
+
+    /// Add a mixer channel, e.g. for UI sounds or voice, played alongside the main channel
+    pub fn add_channel(&self) -> AudioChannel {
+        AudioChannel::new(Arc::clone(&self.audio), Arc::clone(&self.clock))
+    }
+
+    /// Start a new timeline, e.g. after seeking. The next timestamped push is presented shortly after it's made
+    pub fn reset_clock(&self) {
+        self.clock.lock().unwrap().reset();
+    }
+
+    pub fn audio_stats(&self) -> AudioStats {
+        self.main.stats()
+    }
+
+    /// Volume slider and headphone driven gain on all outgoing audio
+    pub fn volume(&self) -> &VolumeControl {
+        &self.volume
+    }
+
+    /// Play `pattern` on the rumble motor, replacing the current pattern
+    pub fn rumble(&self, pattern: Rumble) {
+        self.audio.rumble.lock().unwrap().set(pattern);
+    }
+
+    /// Subscribe to per-frame encoder and timing statistics
+    pub fn stats(&self) -> broadcast::Receiver<FrameStats> {
+        self.stats.subscribe()
+    }
+
+    /// Receive audio from the gamepad microphone, once enabled with [`mic::set_enabled`]
+    pub fn microphone(&self) -> Microphone {
+        Microphone::new(self.mic.subscribe())
     }
 }
 
 struct VideoRunner<T: Frame + Send + Sync> {
-    recv: watch::Receiver<Option<T>>,
+    config: Config,
+    max_payload_size: usize,
+    recv: watch::Receiver<Option<QueuedFrame<T>>>,
     initial: bool,
-    v_seq_id: u16,
+    v_seq_id: SeqId,
     encoder: Encoder,
     v_connection: UdpSocket,
     a_connection: Arc<UdpSocket>,
     tsf: Tsf,
-    next_timestamp: u64,
+    next_timestamp: Timestamp,
     resync: Arc<AtomicBool>,
+    resync_count: u64,
+    stats: broadcast::Sender<FrameStats>,
 }
 
 fn dump_headers(mut file: impl Write) {
@@ -125,12 +253,19 @@ async fn msg_handler(resync: Arc<AtomicBool>) {
 }
 
 impl<T: Frame + Send + Sync + 'static> VideoRunner<T> {
-    fn spawn(recv: watch::Receiver<Option<T>>, audio_queue: Arc<Mutex<VecDeque<u8>>>) {
+    fn spawn(
+        config: Config,
+        recv: watch::Receiver<Option<QueuedFrame<T>>>,
+        audio: Arc<AudioState>,
+        stats: broadcast::Sender<FrameStats>,
+        mic: broadcast::Sender<MicPacket>,
+    ) {
         tokio::task::spawn_blocking(move || {
             let result: Report<Error> = Report::capture(|| {
-                let mut runner = Handle::current().block_on(Self::new(recv))?;
+                let mut runner = Handle::current().block_on(Self::new(config, recv, stats))?;
                 // let mut last_loop = Instant::now();
-                tokio::spawn(audio_loop(runner.a_connection.clone(), audio_queue));
+                tokio::spawn(audio_loop(runner.a_connection.clone(), audio));
+                tokio::spawn(mic_loop(runner.a_connection.clone(), mic, runner.config.mtu()));
                 loop {
                     // println!("since last loop {:?}", last_loop.elapsed());
                     // last_loop = Instant::now();
@@ -141,7 +276,12 @@ impl<T: Frame + Send + Sync + 'static> VideoRunner<T> {
         });
     }
 
-    async fn new(recv: watch::Receiver<Option<T>>) -> Result<Self, Error> {
+    async fn new(
+        config: Config,
+        recv: watch::Receiver<Option<QueuedFrame<T>>>,
+        stats: broadcast::Sender<FrameStats>,
+    ) -> Result<Self, Error> {
+        let max_payload_size = config.max_payload_size().context(ConfigSnafu)?;
         let v_connection =
             UdpSocket::bind("192.168.1.10:50020")
                 .await
@@ -174,27 +314,31 @@ impl<T: Frame + Send + Sync + 'static> VideoRunner<T> {
         let resync = Arc::new(AtomicBool::new(true));
         tokio::spawn(msg_handler(resync.clone()));
         let mut tsf = Tsf::new();
-        let next_timestamp = tsf.timestamp();
+        let next_timestamp = tsf.wire_timestamp();
         Ok(Self {
+            config,
+            max_payload_size,
             recv,
             initial: true,
-            v_seq_id: 0,
+            v_seq_id: SeqId::default(),
             encoder,
             v_connection,
             a_connection,
             tsf,
             next_timestamp,
             resync,
+            resync_count: 0,
+            stats,
         })
     }
 
-    fn make_video_format(ts: u64) -> [u8; 32] {
+    fn make_video_format(ts: Timestamp) -> [u8; 32] {
         let mut packet = [0u8; 32];
         packet[0] = 0x04; // video fmt
         packet[2..4].copy_from_slice(&24u16.to_be_bytes());
         packet[4..8].copy_from_slice(&0x00100000u32.to_le_bytes()); // TODO: why LE?
         // Payload (24 bytes)
-        packet[8..12].copy_from_slice(&(ts as u32).to_le_bytes()); // TODO: why LE?
+        packet[8..12].copy_from_slice(&ts.0.to_le_bytes()); // TODO: why LE?
         packet[28..].copy_from_slice(&[
             0x01, // vid_format
             0x00, 0x00, 0x00, // padding
@@ -218,25 +362,33 @@ impl<T: Frame + Send + Sync + 'static> VideoRunner<T> {
 
     const FRAMERATE: FrameRate = FrameRate::Fifty;
 
-    fn prepare_packets(&mut self, timestamp: u64, resync: bool) -> Result<Vec<Vec<u8>>, Error> {
+    fn prepare_packets(
+        &mut self,
+        timestamp: Timestamp,
+        resync: bool,
+        stats: &mut FrameStats,
+    ) -> Result<Vec<Vec<u8>>, Error> {
         let image = self.recv.borrow_and_update();
-        let Some(im) = &*image else { return Ok(vec![]) };
+        let Some(queued) = &*image else { return Ok(vec![]) };
 
         let init_flag = self.initial;
         self.initial = false;
 
+        let encode_start = Instant::now();
         let (chunks, idr) = self
             .encoder
-            .encode(im.as_image(), resync || init_flag)
+            .encode(queued.frame.as_image(), resync || init_flag)
             .context(EncodingSnafu)?;
+        stats.encode_time = encode_start.elapsed();
         debug_assert!(if resync || init_flag { idr } else { true });
-        if idr {
-            println!("idr");
-        }
         drop(image);
 
+        stats.idr = idr;
+        stats.chunk_sizes = chunks.iter().map(|chunk| chunk.len()).collect();
+        stats.frame_bytes = stats.chunk_sizes.iter().sum();
+
         let mut packets = Vec::new();
-        for (i, mut chunk) in chunks.into_iter().enumerate() {
+        for (i, mut chunk) in chunks.iter().copied().enumerate() {
             debug_assert!(chunk.len() > 0, "empty chunks are possible?");
             let mut first_packet = true;
             let first_chunk = i == 0;
@@ -244,7 +396,7 @@ impl<T: Frame + Send + Sync + 'static> VideoRunner<T> {
 
             while chunk.len() > 0 {
                 let packet;
-                if let Some((before, after)) = chunk.split_at_checked(MAX_PAYLOAD_SIZE) {
+                if let Some((before, after)) = chunk.split_at_checked(self.max_payload_size) {
                     packet = before;
                     chunk = after;
                 } else {
@@ -254,15 +406,19 @@ impl<T: Frame + Send + Sync + 'static> VideoRunner<T> {
 
                 let last_packet = chunk.len() == 0;
                 let seq_id = self.v_seq_id;
-                self.v_seq_id = (seq_id + 1) % 1024;
+                self.v_seq_id = seq_id.next();
                 let mut header = VstrmHeader {
-                    seq_id,
+                    seq_id: seq_id.get(),
                     payload_size: packet.len() as u16,
-                    timestamp: timestamp as u32,
+                    timestamp: timestamp.0,
                     init: init_flag,
                     frame_begin: first_packet && first_chunk,
                     chunk_end: last_packet,
                     frame_end: last_packet && last_chunk,
+                    ext_headers: vec![
+                        ExtOption::ForceDecoding,
+                        ExtOption::NumMbRowsInChunk(ChunkLayout::ROWS_PER_CHUNK),
+                    ],
                     ..VstrmHeader::default()
                 };
                 header
@@ -279,6 +435,7 @@ impl<T: Frame + Send + Sync + 'static> VideoRunner<T> {
                 packets.push(buffer);
             }
         }
+        stats.packets = packets.len();
         Ok(packets)
     }
 
@@ -299,69 +456,91 @@ impl<T: Frame + Send + Sync + 'static> VideoRunner<T> {
 
     fn update_frame(&mut self) -> Result<(), Error> {
         let resync = self.resync.compare_exchange(true, false, Ordering::Relaxed, Ordering::Relaxed).is_ok();
+        if resync {
+            self.resync_count += 1;
+        }
 
-        let video = self.prepare_packets(self.next_timestamp, resync)?;
+        // present new timestamped frames at their own time instead of the next tick
+        if self.recv.has_changed().unwrap_or(false) {
+            let due = self.recv.borrow().as_ref().and_then(|queued| queued.due);
+            if let Some(due) = due
+                && let Schedule::Wait(_) = Schedule::new(due, self.next_timestamp)
+            {
+                self.next_timestamp = due;
+            }
+        }
+
+        let mut stats = FrameStats {
+            timestamp: self.next_timestamp.0,
+            resync,
+            resync_count: self.resync_count,
+            ..FrameStats::default()
+        };
+        let video = self.prepare_packets(self.next_timestamp, resync, &mut stats)?;
         if video.is_empty() {
             return Ok(());
         }
         let audio = Self::make_video_format(self.next_timestamp);
 
-        let current_timestamp = self.tsf.timestamp();
-        if self.next_timestamp > current_timestamp {
-            std::thread::sleep(Duration::from_micros(
-                self.next_timestamp - current_timestamp,
-            ));
-        } else if current_timestamp > self.next_timestamp + 50000 {
-            eprintln!("Behind by more than 50ms, pausing 100ms");
-            self.next_timestamp = current_timestamp + 100000;
+        let current_timestamp = self.tsf.wire_timestamp();
+        stats.timestamp_error = current_timestamp.since(self.next_timestamp) as i64;
+        match Schedule::new(self.next_timestamp, current_timestamp) {
+            Schedule::Wait(duration) => std::thread::sleep(duration),
+            Schedule::Now => {}
+            Schedule::Behind => {
+                eprintln!("Behind by more than 50ms, pausing 100ms");
+                self.next_timestamp = current_timestamp.add_micros(100000);
+            }
+            Schedule::Jumped => {
+                eprintln!("TSF jumped backwards, rescheduling");
+                self.next_timestamp = current_timestamp;
+            }
         }
-        self.next_timestamp += (1000000.0 / Self::FRAMERATE.freq()) as u64;
+        self.next_timestamp = self
+            .next_timestamp
+            .add_micros((1000000.0 / Self::FRAMERATE.freq()) as u32);
+        let send_start = Instant::now();
         Handle::current().block_on(self.send_packets(&audio, video.as_slice()))?;
+        stats.send_time = send_start.elapsed();
+        let _ = self.stats.send(stats);
 
         Ok(())
     }
 }
 
-const SAMPLES_PER_PACKET: usize = 384;
-const BYTES_PER_PACKET: usize = SAMPLES_PER_PACKET * 2 * size_of::<i16>();
-const PACKET_INTERVAL: Duration = Duration::from_millis(8);
-async fn audio_loop(connection: Arc<UdpSocket>, audio_queue: Arc<Mutex<VecDeque<u8>>>) {
-    let mut next_time = tokio::time::Instant::now();
-    let mut tsf = Tsf::new();
-    let mut packet = vec![0u8; 8 + BYTES_PER_PACKET];
-    let mut seq_id = 0u16;
-    packet[0] = 1 << 5;
-    packet[2..4].copy_from_slice(&(BYTES_PER_PACKET as u16).to_be_bytes());
-    loop {
-        packet[0] = (packet[0] & 0b11111100) | ((seq_id >> 8) as u8 & 0b11);
-        packet[1] = seq_id as u8;
-        seq_id = (seq_id + 1) % 1024;
-        {
-            let mut audio = audio_queue.lock().unwrap();
-            let range = ..usize::min(BYTES_PER_PACKET, audio.len());
-            for (dst, src) in packet[8..]
-                .iter_mut()
-                .zip(audio.drain(range).chain(std::iter::repeat(0)))
-            {
-                *dst = src;
-            }
-        }
-        let ts = tsf.timestamp();
-        packet[4..8].copy_from_slice(&(ts as u32).to_le_bytes());
-        connection.send(&packet).await.expect("uh oh");
+/// What to do with a frame scheduled for `next`, given the current time
+#[derive(Debug, PartialEq, Eq)]
+enum Schedule {
+    Wait(Duration),
+    Now,
+    /// more than 50ms late
+    Behind,
+    /// scheduled further ahead than the TSF can explain, it was reset or jumped backwards
+    Jumped,
+}
 
-        next_time += PACKET_INTERVAL;
-        if !next_time.elapsed().is_zero() {
-            eprintln!("audio behind deadline");
+impl Schedule {
+    const MAX_BEHIND: i32 = 50000;
+    const MAX_AHEAD: i32 = 1000000;
+
+    fn new(next: Timestamp, now: Timestamp) -> Self {
+        match next.since(now) {
+            ahead if ahead > Self::MAX_AHEAD => Schedule::Jumped,
+            ahead if ahead > 0 => Schedule::Wait(Duration::from_micros(ahead as u64)),
+            ahead if ahead < -Self::MAX_BEHIND => Schedule::Behind,
+            _ => Schedule::Now,
         }
-        tokio::time::sleep_until(next_time).await;
     }
 }
 
 #[derive(Debug, Snafu)]
 pub enum Error {
+    /// invalid streamer configuration
+    Config { source: ConfigError },
     /// constructing packet header
     Data { source: DataError },
+    /// converting audio
+    Pcm { source: PcmError },
     /// initializing encoder
     EncoderCreate { source: EncoderError },
     /// encoding frame
@@ -385,3 +564,71 @@ pub enum ConnectionType {
     Video,
     Audio,
 }
+
+#[cfg(test)]
+mod test {
+    use crate::video::data::SeqId;
+    use crate::video::tsf::Timestamp;
+    use crate::video::{Frame, Schedule, VideoRunner};
+    use strawberry_x264::Image;
+    use std::time::Duration;
+
+    const FRAME_INTERVAL: u32 = 20000;
+
+    #[test]
+    fn schedule_across_tsf_wrap() {
+        let mut next = Timestamp::from_tsf(u32::MAX as u64 - 3 * FRAME_INTERVAL as u64);
+        let mut tsf = next.0 as u64 - 5000;
+        for _ in 0..10 {
+            let now = Timestamp::from_tsf(tsf);
+            assert_eq!(
+                Schedule::new(next, now),
+                Schedule::Wait(Duration::from_micros(5000))
+            );
+            next = next.add_micros(FRAME_INTERVAL);
+            tsf += FRAME_INTERVAL as u64;
+        }
+        assert!(tsf > u32::MAX as u64);
+    }
+
+    struct NoFrame;
+
+    impl Frame for NoFrame {
+        fn as_image(&self) -> Image<'_> {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn video_format_carries_due_time() {
+        let due = Timestamp(0x1234_5678);
+        let packet = VideoRunner::<NoFrame>::make_video_format(due);
+        assert_eq!(packet[..4], [0x04, 0x00, 0x00, 24]);
+        assert_eq!(packet[8..12], due.0.to_le_bytes());
+        assert_eq!(packet[20..24], 16000u32.to_le_bytes());
+        assert_eq!(packet[28], 0x01);
+    }
+
+    #[test]
+    fn schedule_late_across_tsf_wrap() {
+        let next = Timestamp::from_tsf(u32::MAX as u64 - 1000);
+        let now = Timestamp::from_tsf(u32::MAX as u64 + 30000);
+        assert_eq!(Schedule::new(next, now), Schedule::Now);
+        let now = Timestamp::from_tsf(u32::MAX as u64 + 60000);
+        assert_eq!(Schedule::new(next, now), Schedule::Behind);
+    }
+
+    #[test]
+    fn schedule_tsf_reset() {
+        let next = Timestamp::from_tsf(0x1234_5678_9abc);
+        let now = Timestamp::from_tsf(1000);
+        assert_eq!(Schedule::new(next, now), Schedule::Jumped);
+    }
+
+    #[test]
+    fn seq_id_wrap() {
+        let last = SeqId::new(1023);
+        assert_eq!(last.next(), SeqId::new(0));
+        assert_eq!(SeqId::new(1024), SeqId::new(0));
+    }
+}
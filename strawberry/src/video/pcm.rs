@@ -0,0 +1,320 @@
+use crate::video::audio::{CHANNELS, SAMPLE_RATE};
+use snafu::{ensure, Snafu};
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::f64::consts::PI;
+use std::time::Duration;
+
+/// A PCM sample format that can be sent to the gamepad
+pub trait Sample: Copy {
+    /// The sample scaled to `-1.0..=1.0`
+    fn to_f32(self) -> f32;
+}
+
+impl Sample for i16 {
+    fn to_f32(self) -> f32 {
+        self as f32 / 32768.0
+    }
+}
+
+impl Sample for f32 {
+    fn to_f32(self) -> f32 {
+        self
+    }
+}
+
+/// Borrowed PCM audio in any channel layout. Multichannel audio uses the WAV/SMPTE channel order
+#[derive(Debug, Copy, Clone)]
+pub enum AudioBuffer<'a, S: Sample> {
+    /// samples of all channels interleaved, frame by frame
+    Interleaved { data: &'a [S], channels: usize },
+    /// one slice of samples per channel
+    Planar(&'a [&'a [S]]),
+}
+
+impl<S: Sample> AudioBuffer<'_, S> {
+    pub fn channels(&self) -> usize {
+        match self {
+            AudioBuffer::Interleaved { channels, .. } => *channels,
+            AudioBuffer::Planar(planes) => planes.len(),
+        }
+    }
+
+    pub fn frames(&self) -> usize {
+        match self {
+            AudioBuffer::Interleaved { data, channels } => data.len() / channels.max(&1),
+            AudioBuffer::Planar(planes) => planes.first().map_or(0, |plane| plane.len()),
+        }
+    }
+
+    fn sample(&self, frame: usize, channel: usize) -> f32 {
+        match self {
+            AudioBuffer::Interleaved { data, channels } => data[frame * channels + channel].to_f32(),
+            AudioBuffer::Planar(planes) => planes[channel][frame].to_f32(),
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        ensure!(self.channels() > 0, NoChannelsSnafu);
+        match self {
+            AudioBuffer::Interleaved { data, channels } => ensure!(
+                data.len() % channels == 0,
+                PartialFrameSnafu {
+                    length: data.len(),
+                    channels: *channels,
+                }
+            ),
+            AudioBuffer::Planar(planes) => ensure!(
+                planes.iter().all(|plane| plane.len() == self.frames()),
+                PlaneLengthSnafu
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// Left and right gain of each channel when downmixing, in WAV/SMPTE order
+const DOWNMIX: [(f32, f32); 8] = [
+    (1.0, 0.0),                       // front left
+    (0.0, 1.0),                       // front right
+    (FRAC_1_SQRT_2, FRAC_1_SQRT_2),   // front center
+    (0.0, 0.0),                       // LFE
+    (FRAC_1_SQRT_2, 0.0),             // back left
+    (0.0, FRAC_1_SQRT_2),             // back right
+    (FRAC_1_SQRT_2, 0.0),             // side left
+    (0.0, FRAC_1_SQRT_2),             // side right
+];
+
+fn downmix_gains(channels: usize) -> Vec<(f32, f32)> {
+    match channels {
+        1 => vec![(1.0, 1.0)],
+        2 => vec![(1.0, 0.0), (0.0, 1.0)],
+        _ => {
+            let gains: Vec<_> = (0..channels)
+                .map(|channel| DOWNMIX.get(channel).copied().unwrap_or((0.5, 0.5)))
+                .collect();
+            // keep full scale input on every channel from clipping
+            let left: f32 = gains.iter().map(|(left, _)| left).sum();
+            let right: f32 = gains.iter().map(|(_, right)| right).sum();
+            let scale = 1.0 / f32::max(left, right);
+            gains
+                .into_iter()
+                .map(|(left, right)| (left * scale, right * scale))
+                .collect()
+        }
+    }
+}
+
+/// Windowed sinc resampler for stereo audio
+struct Resampler {
+    /// input samples per output sample
+    step: f64,
+    /// lowpass cutoff relative to the input Nyquist frequency
+    cutoff: f64,
+    history: Vec<[f32; CHANNELS]>,
+    /// position of the next output sample in `history`
+    position: f64,
+}
+
+impl Resampler {
+    /// filter taps on either side of an output sample
+    const TAPS: usize = 16;
+
+    fn new(sample_rate: u32) -> Self {
+        let step = sample_rate as f64 / SAMPLE_RATE as f64;
+        Self {
+            step,
+            cutoff: f64::min(1.0, 1.0 / step),
+            // output 0 lines up with input 0, the zeros are the left context
+            history: vec![[0.0; CHANNELS]; Self::TAPS],
+            position: Self::TAPS as f64,
+        }
+    }
+
+    fn kernel(&self, x: f64) -> f64 {
+        let window = x / Self::TAPS as f64;
+        if window.abs() >= 1.0 {
+            return 0.0;
+        }
+        let blackman = 0.42 + 0.5 * (PI * window).cos() + 0.08 * (2.0 * PI * window).cos();
+        let x = x * self.cutoff;
+        let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+        sinc * blackman
+    }
+
+    /// Resample `input`, returning how many input samples before its start the first output lies
+    fn process(&mut self, input: impl IntoIterator<Item = [f32; CHANNELS]>, output: &mut Vec<[f32; CHANNELS]>) -> f64 {
+        let start = self.history.len() as f64;
+        self.history.extend(input);
+        let lag = start - self.position;
+        while self.position + (Self::TAPS as f64) < self.history.len() as f64 {
+            let center = self.position.floor() as usize;
+            let mut frame = [0.0; CHANNELS];
+            let mut total = 0.0;
+            for k in center + 1 - Self::TAPS..=center + Self::TAPS {
+                let weight = self.kernel(self.position - k as f64);
+                total += weight;
+                for (out, sample) in frame.iter_mut().zip(self.history[k]) {
+                    *out += weight as f32 * sample;
+                }
+            }
+            output.push(frame.map(|sample| sample / total as f32));
+            self.position += self.step;
+        }
+        let consumed = (self.position.floor() as usize + 1).saturating_sub(Self::TAPS);
+        self.history.drain(..consumed);
+        self.position -= consumed as f64;
+        lag
+    }
+}
+
+/// Converts PCM audio of any sample rate and channel layout to the 48 kHz stereo s16 the gamepad plays
+pub struct AudioConverter {
+    sample_rate: u32,
+    channels: usize,
+    gains: Vec<(f32, f32)>,
+    resampler: Option<Resampler>,
+    mixed: Vec<[f32; CHANNELS]>,
+    resampled: Vec<[f32; CHANNELS]>,
+}
+
+impl AudioConverter {
+    pub fn new(sample_rate: u32, channels: usize) -> Result<Self, Error> {
+        ensure!(sample_rate > 0, SampleRateSnafu { sample_rate });
+        ensure!(channels > 0, NoChannelsSnafu);
+        Ok(Self {
+            sample_rate,
+            channels,
+            gains: downmix_gains(channels),
+            resampler: (sample_rate as usize != SAMPLE_RATE).then(|| Resampler::new(sample_rate)),
+            mixed: Vec::new(),
+            resampled: Vec::new(),
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Append the converted samples, interleaved, to `output`. Returns how much earlier than the start
+    /// of `buffer` the first appended sample plays, as resampling holds back the last few input samples
+    pub fn convert<S: Sample>(&mut self, buffer: &AudioBuffer<S>, output: &mut Vec<i16>) -> Result<Duration, Error> {
+        buffer.validate()?;
+        ensure!(
+            buffer.channels() == self.channels,
+            ChannelCountSnafu {
+                expected: self.channels,
+                actual: buffer.channels(),
+            }
+        );
+        self.mixed.clear();
+        self.mixed.extend((0..buffer.frames()).map(|frame| {
+            let mut mixed = [0.0; CHANNELS];
+            for (channel, (left, right)) in self.gains.iter().enumerate() {
+                let sample = buffer.sample(frame, channel);
+                mixed[0] += left * sample;
+                mixed[1] += right * sample;
+            }
+            mixed
+        }));
+        let (frames, lag) = match &mut self.resampler {
+            Some(resampler) => {
+                self.resampled.clear();
+                let lag = resampler.process(self.mixed.drain(..), &mut self.resampled);
+                (&self.resampled, lag)
+            }
+            None => (&self.mixed, 0.0),
+        };
+        quantize(frames, output);
+        Ok(Duration::from_secs_f64(lag.max(0.0) / self.sample_rate as f64))
+    }
+
+    /// Append the samples resampling still holds back, as if the input ended in silence.
+    /// Audio converted afterwards plays after that silence
+    pub fn flush(&mut self, output: &mut Vec<i16>) {
+        if let Some(resampler) = &mut self.resampler {
+            self.resampled.clear();
+            resampler.process(std::iter::repeat_n([0.0; CHANNELS], Resampler::TAPS), &mut self.resampled);
+            quantize(&self.resampled, output);
+        }
+    }
+}
+
+fn quantize(frames: &[[f32; CHANNELS]], output: &mut Vec<i16>) {
+    output.extend(
+        frames
+            .iter()
+            .flatten()
+            .map(|sample| (sample * 32768.0).clamp(i16::MIN as f32, i16::MAX as f32) as i16),
+    );
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("invalid sample rate {sample_rate}"))]
+    SampleRate { sample_rate: u32 },
+    /// audio without channels
+    NoChannels,
+    #[snafu(display("expected {expected} channels, got {actual}"))]
+    ChannelCount { expected: usize, actual: usize },
+    #[snafu(display("{length} interleaved samples don't divide into {channels} channels"))]
+    PartialFrame { length: usize, channels: usize },
+    /// planar channels of different lengths
+    PlaneLength,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::video::pcm::*;
+
+    fn sine(sample_rate: u32, frequency: f64, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (2.0 * PI * frequency * i as f64 / sample_rate as f64).sin() as f32 * 0.5)
+            .collect()
+    }
+
+    #[test]
+    fn passthrough_is_exact() {
+        let mut converter = AudioConverter::new(48000, 2).unwrap();
+        let input = [0i16, 1, -1, 1000, i16::MIN, i16::MAX];
+        let mut output = vec![];
+        converter
+            .convert(&AudioBuffer::Interleaved { data: &input, channels: 2 }, &mut output)
+            .unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn mono_is_duplicated() {
+        let mut converter = AudioConverter::new(48000, 1).unwrap();
+        let mut output = vec![];
+        converter
+            .convert(&AudioBuffer::Planar(&[&[0.5f32, -0.25]]), &mut output)
+            .unwrap();
+        assert_eq!(output, [16384, 16384, -8192, -8192]);
+    }
+
+    #[test]
+    fn resample_keeps_length_and_amplitude() {
+        let mut converter = AudioConverter::new(44100, 1).unwrap();
+        let input = sine(44100, 1000.0, 44100);
+        let mut output = vec![];
+        for block in input.chunks(1000) {
+            converter
+                .convert(&AudioBuffer::Planar(&[block]), &mut output)
+                .unwrap();
+        }
+        let frames = output.len() / 2;
+        assert!((47950..=48000).contains(&frames), "{frames} frames");
+        let expected = sine(48000, 1000.0, frames);
+        let steady = 100..frames - 100;
+        let error = steady
+            .clone()
+            .map(|i| (output[i * 2] as f32 / 32768.0 - expected[i]).abs())
+            .fold(0.0, f32::max);
+        assert!(error < 0.01, "max error {error}");
+    }
+}
@@ -0,0 +1,118 @@
+use std::time::{Duration, Instant};
+
+/// A pattern for the gamepad's rumble motor, made of on and off steps
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rumble {
+    steps: Vec<(bool, Duration)>,
+    repeat: Repeat,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Repeat {
+    Times(u32),
+    Forever,
+}
+
+impl Rumble {
+    pub fn new(steps: impl IntoIterator<Item = (bool, Duration)>, repeat: Repeat) -> Self {
+        Self {
+            steps: steps.into_iter().collect(),
+            repeat,
+        }
+    }
+
+    pub fn off() -> Self {
+        Self::new([], Repeat::Times(0))
+    }
+
+    /// Rumble until replaced by another pattern
+    pub fn on() -> Self {
+        Self::new([(true, Duration::from_secs(1))], Repeat::Forever)
+    }
+
+    pub fn duration(duration: Duration) -> Self {
+        Self::new([(true, duration)], Repeat::Times(1))
+    }
+
+    pub fn pulses(on: Duration, off: Duration, count: u32) -> Self {
+        Self::new([(true, on), (false, off)], Repeat::Times(count))
+    }
+
+    /// Whether the motor runs `elapsed` after the pattern started
+    pub fn is_on(&self, elapsed: Duration) -> bool {
+        let period: Duration = self.steps.iter().map(|(_, duration)| *duration).sum();
+        if period.is_zero() {
+            return false;
+        }
+        let cycle = elapsed.as_nanos() / period.as_nanos();
+        if let Repeat::Times(count) = self.repeat
+            && cycle >= count as u128
+        {
+            return false;
+        }
+        let mut offset = Duration::from_nanos((elapsed.as_nanos() % period.as_nanos()) as u64);
+        for (on, duration) in &self.steps {
+            if offset < *duration {
+                return *on;
+            }
+            offset -= *duration;
+        }
+        false
+    }
+}
+
+impl Default for Rumble {
+    fn default() -> Self {
+        Self::off()
+    }
+}
+
+/// The pattern currently playing
+pub(crate) struct RumbleState {
+    pattern: Rumble,
+    started: Instant,
+}
+
+impl RumbleState {
+    pub fn set(&mut self, pattern: Rumble) {
+        self.pattern = pattern;
+        self.started = Instant::now();
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.pattern.is_on(self.started.elapsed())
+    }
+}
+
+impl Default for RumbleState {
+    fn default() -> Self {
+        Self {
+            pattern: Rumble::off(),
+            started: Instant::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::video::rumble::*;
+
+    #[test]
+    fn pulses() {
+        let ms = Duration::from_millis;
+        let rumble = Rumble::pulses(ms(100), ms(50), 2);
+        assert!(rumble.is_on(ms(0)));
+        assert!(rumble.is_on(ms(99)));
+        assert!(!rumble.is_on(ms(120)));
+        assert!(rumble.is_on(ms(150)));
+        assert!(!rumble.is_on(ms(260)));
+        assert!(!rumble.is_on(ms(310)));
+    }
+
+    #[test]
+    fn on_and_off() {
+        assert!(Rumble::on().is_on(Duration::from_secs(3600)));
+        assert!(!Rumble::off().is_on(Duration::ZERO));
+        assert!(!Rumble::duration(Duration::from_secs(1)).is_on(Duration::from_secs(1)));
+    }
+}
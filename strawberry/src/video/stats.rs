@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+/// Telemetry for a single frame sent to the gamepad
+#[derive(Debug, Clone, Default)]
+pub struct FrameStats {
+    /// Low 32 bits of the TSF the frame was scheduled for, as sent in the vstrm header
+    pub timestamp: u32,
+    /// TSF at send time minus `timestamp` in µs, negative values are slack before the deadline
+    pub timestamp_error: i64,
+    pub idr: bool,
+    /// Whether the gamepad requested a resync before this frame
+    pub resync: bool,
+    /// Total number of resync requests handled since the stream started
+    pub resync_count: u64,
+    /// Encoded size of the frame, without vstrm headers
+    pub frame_bytes: usize,
+    pub chunk_sizes: Vec<usize>,
+    pub packets: usize,
+    pub encode_time: Duration,
+    pub send_time: Duration,
+}
+
+/// Snapshot of the audio queue
+#[derive(Debug, Clone, Default)]
+pub struct AudioStats {
+    /// Audio waiting to be sent
+    pub queued: Duration,
+    /// Times the queue ran dry while playing
+    pub underruns: u64,
+    /// Times pushed audio exceeded the maximum latency
+    pub overruns: u64,
+    /// Sample frames dropped to limit latency or skip late audio
+    pub dropped_frames: u64,
+    /// Current playback speed correction in parts per million
+    pub drift_ppm: f64,
+}
@@ -1,21 +1,26 @@
-use pnet::{datalink::interfaces, ipnetwork::IpNetwork};
+use crate::net::get_interface_of_ipv4;
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
 use std::net::Ipv4Addr;
 use std::str::FromStr;
 
-fn get_interface_of_ipv4(addr: Ipv4Addr) -> Option<String> {
-    let ifa = pnet::datalink::interfaces().into_iter().find(|ifa| {
-        for ip in &ifa.ips {
-            if let IpNetwork::V4(ip) = ip
-                && ip.is_supernet_of(addr.into())
-            {
-                return true;
-            }
-        }
-        false
-    });
-    ifa.map(|ifa| ifa.name)
+/// Low 32 bits of the TSF in µs, as carried in vstrm and astrm headers. Wraps every ~71.6 minutes
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Timestamp(pub u32);
+
+impl Timestamp {
+    pub const fn from_tsf(tsf: u64) -> Self {
+        Self(tsf as u32)
+    }
+
+    /// Signed µs from `earlier` to `self`, correct across wraparound while they're less than ~35 minutes apart
+    pub const fn since(self, earlier: Self) -> i32 {
+        self.0.wrapping_sub(earlier.0) as i32
+    }
+
+    pub const fn add_micros(self, micros: u32) -> Self {
+        Self(self.0.wrapping_add(micros))
+    }
 }
 
 pub struct Tsf {
@@ -37,6 +42,10 @@ impl Tsf {
         self.file.read_exact(&mut buff).expect("read TSF");
         u64::from_ne_bytes(buff)
     }
+
+    pub fn wire_timestamp(&mut self) -> Timestamp {
+        Timestamp::from_tsf(self.timestamp())
+    }
 }
 
 #[cfg(test)]
@@ -46,26 +55,32 @@ mod test {
     use std::time::{Duration, Instant};
 
     #[test]
-    fn get_timestamp() {
-        eprintln!("{}", timestamp());
+    fn timestamp_since_across_wrap() {
+        let before = Timestamp::from_tsf(0x1_ffff_fff0);
+        let after = Timestamp::from_tsf(0x2_0000_0010);
+        assert_eq!(after.0, 0x10);
+        assert_eq!(after.since(before), 0x20);
+        assert_eq!(before.since(after), -0x20);
+        assert_eq!(before.add_micros(0x20), after);
     }
+
     #[test]
-    fn get_timestamps() {
-        let mut last_timestamp = 0;
-        for i in 0..50 {
-            let before = Instant::now();
-            let timestamp = timestamp();
-            assert_ne!(timestamp, last_timestamp);
-            eprintln!("n {} ({:?})", timestamp, before.elapsed());
-            last_timestamp = timestamp;
-            thread::sleep(Duration::from_millis(100));
+    fn timestamp_since_matches_tsf_difference() {
+        let start = 0xffff_0000u64;
+        for step in [0u64, 1, 20000, 0x1_0000, 0x7fff_ffff] {
+            let a = Timestamp::from_tsf(start);
+            let b = Timestamp::from_tsf(start + step);
+            assert_eq!(b.since(a) as i64, step as i64);
+            assert_eq!(a.since(b) as i64, -(step as i64));
         }
     }
+
     #[test]
-    fn get_timestamps_fd() {
+    #[ignore = "needs the gamepad's wifi interface"]
+    fn get_timestamps() {
         let mut tsf = Tsf::new();
         let mut last_timestamp = 0;
-        for i in 0..50 {
+        for _ in 0..50 {
             let before = Instant::now();
             let timestamp = tsf.timestamp();
             assert_ne!(timestamp, last_timestamp);
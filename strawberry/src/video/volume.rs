@@ -0,0 +1,97 @@
+use crate::config::{Error as ConfigError, VolumeCurve};
+use crate::data::InputData;
+use crate::video::audio::AudioState;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VolumeEvent {
+    /// Raw volume slider position
+    pub slider: u8,
+    pub headphones: bool,
+    /// Gain now applied to outgoing audio
+    pub gain: f32,
+}
+
+struct VolumeState {
+    curve: Option<VolumeCurve>,
+    slider: Option<u8>,
+    headphones: bool,
+}
+
+impl VolumeState {
+    fn gain(&self) -> f32 {
+        match (self.curve, self.slider) {
+            (Some(curve), Some(slider)) => curve.gain(slider, self.headphones),
+            _ => 1.0,
+        }
+    }
+}
+
+/// Software volume stage following the gamepad's slider, like the console does.
+/// Feed it input reports with [`Self::update`]
+pub struct VolumeControl {
+    state: Mutex<VolumeState>,
+    audio: Arc<AudioState>,
+    events: broadcast::Sender<VolumeEvent>,
+}
+
+impl VolumeControl {
+    pub(crate) fn new(curve: Option<VolumeCurve>, audio: Arc<AudioState>) -> Self {
+        let (events, _) = broadcast::channel(16);
+        Self {
+            state: Mutex::new(VolumeState {
+                curve,
+                slider: None,
+                headphones: false,
+            }),
+            audio,
+            events,
+        }
+    }
+
+    pub fn update(&self, input: &InputData) {
+        self.change(|state| state.slider = Some(input.audio_volume));
+    }
+
+    /// Headphone insertion isn't decoded from the input report yet, so it's set by hand
+    pub fn set_headphones(&self, headphones: bool) {
+        self.change(|state| state.headphones = headphones);
+    }
+
+    /// Follow the slider with `curve`, or play at full volume with `None`
+    pub fn set_curve(&self, curve: Option<VolumeCurve>) -> Result<(), ConfigError> {
+        if let Some(curve) = &curve {
+            curve.validate()?;
+        }
+        self.change(|state| state.curve = curve);
+        Ok(())
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.state.lock().unwrap().gain()
+    }
+
+    /// Subscribe to slider, headphone and resulting gain changes
+    pub fn events(&self) -> broadcast::Receiver<VolumeEvent> {
+        self.events.subscribe()
+    }
+
+    fn change(&self, change: impl FnOnce(&mut VolumeState)) {
+        let mut state = self.state.lock().unwrap();
+        let before = (state.slider, state.headphones, state.gain());
+        change(&mut state);
+        let gain = state.gain();
+        if before == (state.slider, state.headphones, gain) {
+            return;
+        }
+        self.audio.mixer.lock().unwrap().set_master_gain(gain);
+        if let Some(slider) = state.slider {
+            let _ = self.events.send(VolumeEvent {
+                slider,
+                headphones: state.headphones,
+                gain,
+            });
+        }
+    }
+}